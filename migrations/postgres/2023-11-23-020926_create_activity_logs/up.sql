@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE activity_logs (
+    id SERIAL PRIMARY KEY,
+    payments INTEGER NOT NULL,
+    vouchers INTEGER NOT NULL,
+    pdf_count INTEGER NOT NULL,
+    email_count INTEGER NOT NULL,
+    website_ok BOOLEAN NOT NULL,
+    slack_sent BOOLEAN NOT NULL,
+    email_sent BOOLEAN NOT NULL,
+    datetime TEXT DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')
+);
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE silences (
+    id SERIAL PRIMARY KEY,
+    check_name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    expires_at TEXT,
+    reason TEXT,
+    created_by TEXT,
+    created_at TEXT DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')
+);
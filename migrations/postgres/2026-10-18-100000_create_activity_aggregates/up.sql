@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE activity_aggregates (
+    id SERIAL PRIMARY KEY,
+    resolution TEXT NOT NULL,
+    period_start TEXT NOT NULL,
+    name TEXT NOT NULL,
+    samples INTEGER NOT NULL,
+    min_value BIGINT,
+    max_value BIGINT,
+    avg_value DOUBLE PRECISION,
+    ok_count INTEGER NOT NULL,
+    warning_count INTEGER NOT NULL,
+    alert_count INTEGER NOT NULL
+);
+
+CREATE UNIQUE INDEX activity_aggregates_bucket ON activity_aggregates(resolution, period_start, name);
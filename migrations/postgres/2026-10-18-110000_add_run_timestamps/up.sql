@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- Timestamps are RFC 3339 in UTC (e.g. 2026-10-18T08:00:00Z) so they sort and compare as text
+ALTER TABLE activity_logs ADD COLUMN started_at TEXT;
+ALTER TABLE activity_logs ADD COLUMN finished_at TEXT;
+ALTER TABLE activity_logs ADD COLUMN duration_ms INTEGER;
+ALTER TABLE activity_logs ADD COLUMN threshold_profile TEXT;
+
+UPDATE activity_logs SET started_at = replace(datetime, ' ', 'T') || 'Z' WHERE datetime IS NOT NULL;
+
+CREATE INDEX activity_logs_started_at ON activity_logs(started_at);
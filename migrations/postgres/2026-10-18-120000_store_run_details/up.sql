@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE activity_logs ADD COLUMN slack_message TEXT;
+ALTER TABLE activity_logs ADD COLUMN email_body TEXT;
+
+ALTER TABLE check_results ADD COLUMN message TEXT;
+ALTER TABLE check_results ADD COLUMN url TEXT;
+ALTER TABLE check_results ADD COLUMN value TEXT;
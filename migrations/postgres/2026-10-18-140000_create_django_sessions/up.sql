@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE django_sessions (
+    login_url TEXT PRIMARY KEY NOT NULL,
+    cookies TEXT NOT NULL,
+    saved_at TEXT NOT NULL
+);
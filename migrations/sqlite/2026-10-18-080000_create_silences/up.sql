@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE silences (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    check_name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    expires_at TEXT,
+    reason TEXT,
+    created_by TEXT,
+    created_at TEXT DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE check_results (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    log_id INTEGER NOT NULL REFERENCES activity_logs(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    status TEXT NOT NULL
+);
+
+CREATE INDEX check_results_log_id ON check_results(log_id);
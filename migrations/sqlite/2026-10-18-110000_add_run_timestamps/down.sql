@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX activity_logs_started_at;
+ALTER TABLE activity_logs DROP COLUMN threshold_profile;
+ALTER TABLE activity_logs DROP COLUMN duration_ms;
+ALTER TABLE activity_logs DROP COLUMN finished_at;
+ALTER TABLE activity_logs DROP COLUMN started_at;
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE check_results DROP COLUMN value;
+ALTER TABLE check_results DROP COLUMN url;
+ALTER TABLE check_results DROP COLUMN message;
+
+ALTER TABLE activity_logs DROP COLUMN email_body;
+ALTER TABLE activity_logs DROP COLUMN slack_message;
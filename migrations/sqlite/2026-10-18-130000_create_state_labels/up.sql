@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE state_labels (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    source TEXT NOT NULL,
+    column_name TEXT NOT NULL,
+    label TEXT NOT NULL,
+    first_seen TEXT NOT NULL,
+    last_seen TEXT NOT NULL,
+    last_count INTEGER NOT NULL,
+    UNIQUE (source, column_name, label)
+);
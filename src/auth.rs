@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+use http_auth_basic::Credentials;
+use log::{info, warn};
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, AUTHORIZATION, REFERER};
+use reqwest::{Response, StatusCode, Url};
+use scraper::{Html, Selector};
+use tokio::sync::Mutex;
+
+use crate::config::{Source, SourceAuth, SourceFormat};
+use crate::{logging, secrets};
+
+pub type AuthError = Box<dyn Error + Send + Sync>;
+
+/// The source answered with 401/403 or a login page instead of the data
+#[derive(Debug)]
+pub struct InvalidCredentials {
+    pub(crate) url: String,
+    pub(crate) reason: String,
+}
+
+impl Display for InvalidCredentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.reason, self.url)
+    }
+}
+
+impl Error for InvalidCredentials {}
+
+/// HTTP client shared by the sources of a run: one cookie jar, one Django login per
+/// `login_url`, secrets loaded once
+pub struct Session {
+    client: reqwest::Client,
+    jar: Arc<Jar>,
+    /// Logins of this run per `login_url`, 0 for a session restored from a previous run
+    logins: Mutex<HashMap<String, u32>>,
+    /// `Cookie` headers of the logins of this run, to be stored for the next runs
+    renewed: Mutex<HashMap<String, String>>,
+    secrets: Mutex<HashMap<String, String>>,
+}
+
+fn sensitive(value: &str) -> Result<HeaderValue, AuthError> {
+    let mut value = HeaderValue::from_str(value)?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
+fn csrf_token(login_page: &str) -> Option<String> {
+    let document = Html::parse_document(login_page);
+    let selector = Selector::parse("input[name=csrfmiddlewaretoken]").unwrap();
+    document
+        .select(&selector)
+        .next()
+        .and_then(|input| input.value().attr("value"))
+        .map(|token| token.to_string())
+}
+
+/// Django answers with (or redirects to) the login form when the session is missing or expired
+fn is_login_page(url: &Url, login_url: &str) -> bool {
+    Url::parse(login_url).is_ok_and(|login_url| url.path() == login_url.path())
+}
+
+/// Login pages of sources without a configured `login_url`, e.g. `/admin/login/?next=...`
+pub fn is_login_redirect(url: &Url) -> bool {
+    url.path().trim_end_matches('/').ends_with("/login")
+}
+
+/// A page with a password field is a login form, never the data we asked for
+pub fn is_login_form(html: &str) -> bool {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("form input[type=password]").unwrap();
+    document.select(&selector).next().is_some()
+}
+
+impl Session {
+    /// `saved` maps login URLs to the `Cookie` header of their last login
+    pub fn new(saved: &HashMap<String, String>) -> Session {
+        let jar = Arc::new(Jar::default());
+        let mut logins = HashMap::new();
+        for (login_url, cookies) in saved {
+            let Ok(url) = Url::parse(login_url) else {
+                warn!(
+                    "Ignoring the stored session of an invalid login URL {}",
+                    login_url
+                );
+                continue;
+            };
+            // Django's cookies apply to the whole site, not only to the login page
+            for cookie in cookies.split("; ") {
+                logging::redact(&[cookie]);
+                jar.add_cookie_str(&format!("{}; Path=/", cookie), &url);
+            }
+            logins.insert(login_url.clone(), 0);
+        }
+
+        Session {
+            client: reqwest::Client::builder()
+                .cookie_provider(jar.clone())
+                .build()
+                .expect("the HTTP client configuration is valid"),
+            jar,
+            logins: Mutex::new(logins),
+            renewed: Mutex::new(HashMap::new()),
+            secrets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Login URL -> `Cookie` header of the sessions opened by this run
+    pub async fn renewed(&self) -> HashMap<String, String> {
+        self.renewed.lock().await.clone()
+    }
+
+    pub async fn secret(&self, name: &str) -> Result<String, AuthError> {
+        let mut cache = self.secrets.lock().await;
+        if let Some(value) = cache.get(name) {
+            return Ok(value.clone());
+        }
+
+        let value = secrets::load(name)
+            .await?
+            .ok_or_else(|| format!("{} is not set", name))?;
+        cache.insert(name.to_string(), value.clone());
+        Ok(value)
+    }
+
+    async fn headers(&self, source: &Source) -> Result<HeaderMap, AuthError> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &source.headers {
+            headers.insert(HeaderName::try_from(name)?, HeaderValue::from_str(value)?);
+        }
+        if source.format == SourceFormat::Json && !headers.contains_key(ACCEPT) {
+            headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        }
+
+        match &source.auth {
+            SourceAuth::None | SourceAuth::Django { .. } => {}
+            SourceAuth::Bearer { token_secret } => {
+                let token = self.secret(token_secret).await?;
+                headers.insert(AUTHORIZATION, sensitive(&format!("Bearer {}", token))?);
+            }
+            SourceAuth::Basic {
+                username_secret,
+                password_secret,
+            } => {
+                let username = self.secret(username_secret).await?;
+                let password = self.secret(password_secret).await?;
+                let header = Credentials::new(&username, &password).as_http_header();
+                logging::redact(&[&header]);
+                headers.insert(AUTHORIZATION, sensitive(&header)?);
+            }
+            SourceAuth::Headers { secret_headers } => {
+                for (name, secret_name) in secret_headers {
+                    let value = self.secret(secret_name).await?;
+                    headers.insert(HeaderName::try_from(name)?, sensitive(&value)?);
+                }
+            }
+        }
+
+        Ok(headers)
+    }
+
+    async fn login(
+        &self,
+        login_url: &str,
+        username_secret: &str,
+        password_secret: &str,
+    ) -> Result<(), AuthError> {
+        let username = self.secret(username_secret).await?;
+        let password = self.secret(password_secret).await?;
+
+        // The GET sets the `csrftoken` cookie, the form carries the matching token
+        let login_page = self
+            .client
+            .get(login_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let csrf = csrf_token(&login_page).ok_or("no csrfmiddlewaretoken in the login form")?;
+
+        let res = self
+            .client
+            .post(login_url)
+            .header(REFERER, login_url)
+            .form(&[
+                ("csrfmiddlewaretoken", csrf.as_str()),
+                ("username", username.as_str()),
+                ("password", password.as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?;
+
+        // A failed login renders the form again instead of redirecting
+        if is_login_page(res.url(), login_url) {
+            return Err(InvalidCredentials {
+                url: login_url.to_string(),
+                reason: "Django login rejected".to_string(),
+            }
+            .into());
+        }
+
+        info!("Logged in to {}", login_url);
+        if let Some(cookies) = self.jar.cookies(&Url::parse(login_url)?) {
+            let cookies = cookies.to_str()?.to_string();
+            logging::redact(&cookies.split("; ").collect::<Vec<_>>());
+            self.renewed
+                .lock()
+                .await
+                .insert(login_url.to_string(), cookies);
+        }
+        Ok(())
+    }
+
+    /// Logs in unless a previous source of this run already did or the session was restored.
+    /// `expired` is the login a request was rejected with: sources rejected together log in
+    /// once. Returns the login requests are sent with, `None` without Django auth
+    async fn ensure_login(
+        &self,
+        source: &Source,
+        expired: Option<u32>,
+    ) -> Result<Option<u32>, AuthError> {
+        let SourceAuth::Django {
+            login_url,
+            username_secret,
+            password_secret,
+        } = &source.auth
+        else {
+            return Ok(None);
+        };
+
+        // Held during the login so concurrent sources wait for it instead of logging in too
+        let mut logins = self.logins.lock().await;
+        let current = logins.get(login_url).copied();
+        if current.is_none() || current == expired {
+            self.login(login_url, username_secret, password_secret)
+                .await?;
+            logins.insert(login_url.clone(), current.map_or(1, |login| login + 1));
+        }
+        Ok(logins.get(login_url).copied())
+    }
+
+    pub async fn get(&self, url: &str, source: &Source) -> Result<Response, AuthError> {
+        let login = self.ensure_login(source, None).await?;
+        let headers = self.headers(source).await?;
+        let res = self.client.get(url).headers(headers.clone()).send().await?;
+
+        let SourceAuth::Django { login_url, .. } = &source.auth else {
+            return Ok(res);
+        };
+        let is_expired = is_login_page(res.url(), login_url)
+            || res.status() == StatusCode::UNAUTHORIZED
+            || res.status() == StatusCode::FORBIDDEN;
+        if !is_expired {
+            return Ok(res);
+        }
+
+        info!("Session for {} expired, logging in again", login_url);
+        self.ensure_login(source, login).await?;
+        Ok(self.client.get(url).headers(headers).send().await?)
+    }
+}
@@ -0,0 +1,547 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use chrono_tz::Europe::Paris;
+use log::LevelFilter;
+use serde::{Deserialize, Deserializer};
+
+use crate::validators::{ThresholdProfile, CHECK_NAMES};
+
+/// Optional settings that don't fit in environment variables
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub(crate) maintenance: Vec<MaintenanceWindow>,
+    pub(crate) retention: Option<Retention>,
+    #[serde(default)]
+    pub(crate) logging: Logging,
+    /// Keyed like the URLs: payments, vouchers, paid_vouchers, purchase_website, celery.
+    /// Completed with `default_sources`
+    #[serde(default)]
+    pub(crate) sources: HashMap<String, Source>,
+    /// Failing rows listed under a check in alerts
+    #[serde(default = "default_drill_down_rows")]
+    pub(crate) drill_down_rows: usize,
+    /// Extra checks alerting on rows left too long in a state
+    #[serde(default)]
+    pub(crate) stuck: Vec<StuckRule>,
+    /// Extra checks alerting on rows without counterpart in another source
+    #[serde(default)]
+    pub(crate) reconcile: Vec<ReconcileRule>,
+}
+
+/// Rows of a source, optionally only those in some states
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RowSet {
+    /// One of `ROW_SOURCES`
+    pub(crate) source: String,
+    /// Admin column joined on, e.g. `product_code_link`
+    pub(crate) key: String,
+    /// Admin column filtered on, e.g. `state`
+    pub(crate) column: Option<String>,
+    /// Every row when empty
+    #[serde(default)]
+    pub(crate) states: Vec<String>,
+}
+
+impl RowSet {
+    pub fn columns(&self) -> Vec<String> {
+        let mut columns = vec![self.key.clone()];
+        columns.extend(self.column.clone());
+        columns
+    }
+}
+
+/// Every row of `from` must have a row of `to` with the same key, e.g. every validated
+/// payment a paid voucher. Only fetched rows are joined, so `to` should cover a longer window
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ReconcileRule {
+    /// Check name, e.g. "Payments have vouchers"
+    pub(crate) name: String,
+    pub(crate) from: RowSet,
+    pub(crate) to: RowSet,
+}
+
+/// e.g. a paid voucher still without PDF 15 minutes after its creation
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct StuckRule {
+    /// Check name, e.g. "PDF generation"
+    pub(crate) name: String,
+    /// One of `ROW_SOURCES`
+    pub(crate) source: String,
+    /// Admin column holding the state, e.g. `has_pdf`
+    pub(crate) column: String,
+    /// Non-terminal states, e.g. `["No"]`
+    pub(crate) states: Vec<String>,
+    /// Admin column holding the date the row entered the state, e.g. `created`
+    pub(crate) since: String,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub(crate) sla: Duration,
+}
+
+fn default_drill_down_rows() -> usize {
+    5
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            maintenance: Vec::new(),
+            retention: None,
+            logging: Logging::default(),
+            sources: default_sources(),
+            drill_down_rows: default_drill_down_rows(),
+            stuck: Vec::new(),
+            reconcile: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Built-in checks followed by the configured ones
+    pub fn check_names(&self) -> Vec<&str> {
+        let mut names = CHECK_NAMES.to_vec();
+        names.extend(self.stuck.iter().map(|rule| rule.name.as_str()));
+        names.extend(self.reconcile.iter().map(|rule| rule.name.as_str()));
+        names
+    }
+
+    /// Sources missing from the config send `API_TOKEN` as is in the `Authorization` header
+    pub fn source(&self, key: &str) -> Source {
+        self.sources.get(key).cloned().unwrap_or_else(|| Source {
+            auth: SourceAuth::Headers {
+                secret_headers: BTreeMap::from([(
+                    "Authorization".to_string(),
+                    "API_TOKEN".to_string(),
+                )]),
+            },
+            ..Source::default()
+        })
+    }
+}
+
+/// Sources set up out of the box, a `[sources.<key>]` section replaces them.
+/// Celery is behind basic auth, `CELERY_USERNAME` / `CELERY_PASSWORD`
+fn default_sources() -> HashMap<String, Source> {
+    HashMap::from([(
+        "celery".to_string(),
+        Source {
+            headers: BTreeMap::from([("Content-Type".to_string(), "application/json".to_string())]),
+            auth: SourceAuth::Basic {
+                username_secret: "CELERY_USERNAME".to_string(),
+                password_secret: "CELERY_PASSWORD".to_string(),
+            },
+            ..Source::default()
+        },
+    )])
+}
+
+/// How to authenticate against a source. `*_secret` fields name secrets loaded with
+/// `secrets::load`, so `NAME_FILE`, `NAME_COMMAND` and `NAME_VAULT` work too
+#[derive(Deserialize, Clone, Default)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SourceAuth {
+    #[default]
+    None,
+    Bearer {
+        token_secret: String,
+    },
+    Basic {
+        username_secret: String,
+        password_secret: String,
+    },
+    /// Header name -> secret name, e.g. `Authorization = "API_TOKEN"`
+    Headers {
+        secret_headers: BTreeMap<String, String>,
+    },
+    /// Django admin login form, sources sharing a `login_url` share the session
+    Django {
+        login_url: String,
+        username_secret: String,
+        password_secret: String,
+    },
+}
+
+/// Sources made of changelist rows, which can also come from a JSON API or a database
+pub const ROW_SOURCES: [&str; 3] = ["payments", "vouchers", "paid_vouchers"];
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceFormat {
+    /// Django admin changelist, one `td.field-<column>` cell per column
+    #[default]
+    Html,
+    /// JSON document holding an array of rows, e.g. a DRF list endpoint
+    Json,
+    /// Read-only `query` on the `database_secret` database, columns named after admin columns
+    Sql,
+}
+
+/// JSON pointer of a column in each row, optionally with labels for its values
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Field {
+    Path(String),
+    Mapped {
+        path: String,
+        /// API value -> admin label, e.g. `validated = "Validated"`
+        #[serde(default)]
+        values: BTreeMap<String, String>,
+    },
+}
+
+impl Field {
+    pub fn path(&self) -> &str {
+        match self {
+            Field::Path(path) | Field::Mapped { path, .. } => path,
+        }
+    }
+
+    pub fn label(&self, value: String) -> String {
+        match self {
+            Field::Mapped { values, .. } => values.get(&value).cloned().unwrap_or(value),
+            Field::Path(_) => value,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Source {
+    /// Extra headers sent as is
+    #[serde(default)]
+    pub(crate) headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub(crate) auth: SourceAuth,
+    #[serde(default)]
+    pub(crate) format: SourceFormat,
+    /// JSON pointer to the array of rows, the whole document when empty
+    #[serde(default)]
+    pub(crate) rows: String,
+    /// Admin column (`state`, `has_pdf`...) -> field of a JSON row, same name when missing
+    #[serde(default)]
+    pub(crate) fields: BTreeMap<String, Field>,
+    /// Secret holding the database URL of an SQL source, e.g. a read replica
+    pub(crate) database_secret: Option<String>,
+    /// `{window_start}` is replaced by the quoted start of the `window`
+    pub(crate) query: Option<String>,
+    pub(crate) window: Option<Window>,
+    /// Identifying columns listed for failing rows, see `parser::DEFAULT_DETAILS`
+    pub(crate) details: Option<Vec<String>>,
+    /// Admin change page of a JSON or SQL row, `{id}` is replaced by its `id` column
+    pub(crate) change_url: Option<String>,
+}
+
+/// Only the rows of the last `duration`, e.g. `window = { duration = "1h" }`
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Window {
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub(crate) duration: Duration,
+    /// Query parameter set to the window start, a Django admin date filter by default
+    #[serde(default = "default_window_param")]
+    pub(crate) param: String,
+    /// Fewer rows in the window is a warning
+    #[serde(default)]
+    pub(crate) expected: ExpectedRows,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(deny_unknown_fields, default)]
+pub struct ExpectedRows {
+    pub(crate) day: usize,
+    pub(crate) night: usize,
+}
+
+impl ExpectedRows {
+    pub fn at(&self, profile: ThresholdProfile) -> usize {
+        match profile {
+            ThresholdProfile::Day => self.day,
+            ThresholdProfile::Night => self.night,
+        }
+    }
+}
+
+fn default_window_param() -> String {
+    "created__gte".to_string()
+}
+
+fn deserialize_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    humantime::parse_duration(&text).map_err(serde::de::Error::custom)
+}
+
+/// Text of a bare TOML date/time (`08:00:00`) or of a quoted one (`"08:00:00"`)
+fn date_text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match toml::Value::deserialize(deserializer)? {
+        toml::Value::Datetime(datetime) => Ok(datetime.to_string()),
+        toml::Value::String(text) => Ok(text),
+        other => Err(serde::de::Error::custom(format!(
+            "expected a date or time, got a {}",
+            other.type_str()
+        ))),
+    }
+}
+
+fn deserialize_datetime<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<DateTime<FixedOffset>>, D::Error> {
+    let text = date_text(deserializer)?;
+    text.parse()
+        .map(Some)
+        .map_err(|e| serde::de::Error::custom(format!("invalid date `{}`: {}", text, e)))
+}
+
+fn deserialize_time<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<NaiveTime>, D::Error> {
+    let text = date_text(deserializer)?;
+    text.parse()
+        .map(Some)
+        .map_err(|e| serde::de::Error::custom(format!("invalid time `{}`: {}", text, e)))
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, with the `run_id` and `check` fields when known
+    Json,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogOutput {
+    Stderr,
+    File,
+    Syslog,
+    Journald,
+}
+
+/// Defaults to text logs at info level in `logs/beebot.log`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Logging {
+    pub(crate) level: LevelFilter,
+    pub(crate) format: LogFormat,
+    pub(crate) output: LogOutput,
+    /// Directory of the log file and its rotated copies
+    pub(crate) directory: String,
+    /// Rotate the file once it grows past this size
+    pub(crate) max_size_mb: u64,
+    /// Rotate the file once it is older than this
+    pub(crate) max_age_hours: u64,
+    /// Delete rotated files older than this
+    pub(crate) retention_days: u64,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging {
+            level: LevelFilter::Info,
+            format: LogFormat::Text,
+            output: LogOutput::File,
+            directory: "logs".to_string(),
+            max_size_mb: 10,
+            max_age_hours: 24,
+            retention_days: 30,
+        }
+    }
+}
+
+/// `report`, `history` and `export` only read raw runs, the weekly report needs a week of them
+const MIN_RAW_DAYS: u32 = 7;
+
+/// Raw runs -> hourly aggregates -> daily aggregates, daily ones are kept forever. Only
+/// `db maintain` reads the aggregates, the other commands see the last `raw_days` of runs
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Retention {
+    /// Days of raw runs to keep, at least `MIN_RAW_DAYS`
+    pub(crate) raw_days: u32,
+    /// Days of hourly aggregates to keep, must be at least `raw_days`
+    pub(crate) hourly_days: u32,
+    /// Hours between two automatic maintenances in daemon mode
+    #[serde(default = "default_maintenance_interval_hours")]
+    pub(crate) interval_hours: u64,
+}
+
+fn default_maintenance_interval_hours() -> u64 {
+    24
+}
+
+/// Either a one-off window (`start`/`end`) or a weekly one (`days`, `from`/`to` in Paris time)
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceWindow {
+    /// Check names, every check when empty
+    #[serde(default)]
+    pub(crate) checks: Vec<String>,
+    pub(crate) reason: Option<String>,
+    /// TOML offset datetimes, quoted or not
+    #[serde(default, deserialize_with = "deserialize_datetime")]
+    pub(crate) start: Option<DateTime<FixedOffset>>,
+    #[serde(default, deserialize_with = "deserialize_datetime")]
+    pub(crate) end: Option<DateTime<FixedOffset>>,
+    /// Every day when empty
+    #[serde(default)]
+    pub(crate) days: Vec<Weekday>,
+    /// TOML local times, quoted or not
+    #[serde(default, deserialize_with = "deserialize_time")]
+    pub(crate) from: Option<NaiveTime>,
+    #[serde(default, deserialize_with = "deserialize_time")]
+    pub(crate) to: Option<NaiveTime>,
+}
+
+impl MaintenanceWindow {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            return start <= now && now < end;
+        }
+
+        let (Some(from), Some(to)) = (self.from, self.to) else {
+            return false;
+        };
+        let paris_time = now.with_timezone(&Paris);
+        let time = paris_time.time();
+        let runs_on = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+
+        if from <= to {
+            runs_on(paris_time.weekday()) && from <= time && time < to
+        } else {
+            // Window spanning midnight, e.g. 23:00 -> 01:00
+            (runs_on(paris_time.weekday()) && time >= from)
+                || (runs_on(paris_time.weekday().pred()) && time < to)
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match (self.start, self.end, self.from, self.to) {
+            (Some(start), Some(end), None, None) if start < end => Ok(()),
+            (Some(_), Some(_), None, None) => Err("`start` must be before `end`".to_string()),
+            (None, None, Some(_), Some(_)) => Ok(()),
+            _ => Err("expected either `start` and `end`, or `from` and `to`".to_string()),
+        }
+    }
+}
+
+/// `None` without config file. Runs before `logging::init`, which needs the config, so
+/// nothing is logged here: errors are returned and reported by the caller
+pub fn load_config(path: &str) -> Result<Option<Config>, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read config file {}: {}", path, e)),
+    };
+
+    let mut config: Config =
+        toml::from_str(&content).map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+    for (key, source) in default_sources() {
+        config.sources.entry(key).or_insert(source);
+    }
+
+    for (index, window) in config.maintenance.iter().enumerate() {
+        if let Err(e) = window.validate() {
+            return Err(format!(
+                "Invalid maintenance window #{} in {}: {}",
+                index + 1,
+                path,
+                e
+            ));
+        }
+    }
+
+    if let Some(retention) = &config.retention {
+        if retention.raw_days < MIN_RAW_DAYS
+            || retention.hourly_days < retention.raw_days
+            || retention.interval_hours == 0
+        {
+            return Err(format!(
+                "Invalid retention in {}: expected {} <= raw_days <= hourly_days and interval_hours > 0",
+                path, MIN_RAW_DAYS
+            ));
+        }
+    }
+
+    for (key, source) in &config.sources {
+        if source.format != SourceFormat::Html && !ROW_SOURCES.contains(&key.as_str()) {
+            return Err(format!(
+                "Invalid source {} in {}: the json and sql formats are only supported for {}",
+                key,
+                path,
+                ROW_SOURCES.join(", ")
+            ));
+        }
+        if source.format == SourceFormat::Sql
+            && (source.database_secret.is_none() || source.query.is_none())
+        {
+            return Err(format!(
+                "Invalid source {} in {}: the sql format needs `database_secret` and `query`",
+                key, path
+            ));
+        }
+    }
+
+    for rule in &config.stuck {
+        if rule.states.is_empty() || rule.sla.is_zero() {
+            return Err(format!(
+                "Invalid stuck rule {} in {}: `states` and `sla` can't be empty",
+                rule.name, path
+            ));
+        }
+        if !ROW_SOURCES.contains(&rule.source.as_str()) {
+            return Err(format!(
+                "Invalid stuck rule {} in {}: `source` must be one of {}",
+                rule.name,
+                path,
+                ROW_SOURCES.join(", ")
+            ));
+        }
+    }
+
+    for rule in &config.reconcile {
+        for rows in [&rule.from, &rule.to] {
+            if !ROW_SOURCES.contains(&rows.source.as_str()) {
+                return Err(format!(
+                    "Invalid reconcile rule {} in {}: sources must be among {}",
+                    rule.name,
+                    path,
+                    ROW_SOURCES.join(", ")
+                ));
+            }
+            if rows.column.is_none() != rows.states.is_empty() {
+                return Err(format!(
+                    "Invalid reconcile rule {} in {}: `column` and `states` go together",
+                    rule.name, path
+                ));
+            }
+        }
+    }
+
+    let check_names = config.check_names();
+    for (index, name) in check_names.iter().enumerate() {
+        if check_names[..index].contains(name) {
+            return Err(format!(
+                "Invalid config {}: several checks are named {}",
+                path, name
+            ));
+        }
+    }
+
+    let logging = &config.logging;
+    if logging.max_size_mb == 0 || logging.max_age_hours == 0 || logging.retention_days == 0 {
+        return Err(format!(
+            "Invalid logging in {}: max_size_mb, max_age_hours and retention_days must be positive",
+            path
+        ));
+    }
+
+    Ok(Some(config))
+}
@@ -0,0 +1,211 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::config::Config;
+use crate::db::{insert_silence, load_db};
+use crate::retention::run_maintenance;
+use crate::silence::{describe, new_silence, SilenceKind};
+use crate::slack;
+use crate::utils::Environment;
+
+/// Slack rejects replays older than five minutes, so do we
+const MAX_REQUEST_AGE_SECONDS: i64 = 60 * 5;
+
+struct DaemonState {
+    db_url: String,
+    signing_secret: String,
+}
+
+pub async fn serve(env: &Environment, config: &Config) {
+    if env.slack_signing_secret.is_empty() {
+        error!("SLACK_SIGNING_SECRET is not set, refusing to start the daemon");
+        return;
+    }
+
+    let addr: SocketAddr = match env.listen_addr.parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            error!("Invalid LISTEN_ADDR {}: {}", env.listen_addr, e);
+            return;
+        }
+    };
+
+    if let Some(retention) = config.retention.clone() {
+        let db_url = env.db_url.clone();
+        let period = StdDuration::from_secs(retention.interval_hours * 3600);
+        info!(
+            "Database maintenance scheduled every {}h",
+            retention.interval_hours
+        );
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let db_url = db_url.clone();
+                let retention = retention.clone();
+                let maintenance = move || run_maintenance(&db_url, Some(&retention));
+                if let Err(e) = tokio::task::spawn_blocking(maintenance).await {
+                    error!("Database maintenance task failed: {}", e);
+                }
+            }
+        });
+    }
+
+    let state = Arc::new(DaemonState {
+        db_url: env.db_url.clone(),
+        signing_secret: env.slack_signing_secret.clone(),
+    });
+
+    let app = Router::new()
+        .route("/slack/interactions", post(handle_interaction))
+        .with_state(state);
+
+    info!("Listening for Slack interactions on {}", addr);
+    if let Err(e) = axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await
+    {
+        error!("Daemon stopped: {}", e);
+    }
+}
+
+fn is_signature_valid(headers: &HeaderMap, body: &str, signing_secret: &str) -> bool {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    let (Some(timestamp), Some(signature)) = (
+        header("X-Slack-Request-Timestamp"),
+        header("X-Slack-Signature"),
+    ) else {
+        return false;
+    };
+
+    match timestamp.parse::<i64>() {
+        Ok(ts) if (Utc::now().timestamp() - ts).abs() <= MAX_REQUEST_AGE_SECONDS => {}
+        _ => return false,
+    }
+
+    let Some(Ok(expected)) = signature.strip_prefix("v0=").map(hex::decode) else {
+        return false;
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(signing_secret.as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(format!("v0:{}:{}", timestamp, body).as_bytes());
+    mac.verify_slice(&expected).is_ok()
+}
+
+fn parse_action(action_id: &str) -> Option<(SilenceKind, Option<Duration>)> {
+    match action_id {
+        "ack" => Some((SilenceKind::Ack, None)),
+        "silence_1h" => Some((SilenceKind::Silence, Some(Duration::hours(1)))),
+        "silence_until_resolved" => Some((SilenceKind::Silence, None)),
+        _ => None,
+    }
+}
+
+async fn handle_interaction(
+    State(state): State<Arc<DaemonState>>,
+    headers: HeaderMap,
+    body: String,
+) -> StatusCode {
+    if !is_signature_valid(&headers, &body, &state.signing_secret) {
+        warn!("Rejected Slack interaction with an invalid signature");
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let form: Vec<(String, String)> = serde_urlencoded::from_str(&body).unwrap_or_default();
+    let payload = match form.iter().find(|(key, _)| key == "payload") {
+        Some((_, payload)) => serde_json::from_str::<Value>(payload),
+        None => return StatusCode::BAD_REQUEST,
+    };
+    let Ok(payload) = payload else {
+        return StatusCode::BAD_REQUEST;
+    };
+
+    let user = payload["user"]["username"]
+        .as_str()
+        .or(payload["user"]["name"].as_str())
+        .map(|name| name.to_string());
+    let response_url = payload["response_url"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    let actions = payload["actions"].as_array().cloned().unwrap_or_default();
+    let db_url = state.db_url.clone();
+
+    // Slack wants an answer within 3 seconds, the silences are stored and confirmed after it
+    tokio::spawn(async move {
+        let store = move || store_silences(&db_url, actions, user);
+        let confirmations = match tokio::task::spawn_blocking(store).await {
+            Ok(confirmations) => confirmations,
+            Err(e) => {
+                error!("Slack interaction task failed: {}", e);
+                return;
+            }
+        };
+        if response_url.is_empty() {
+            return;
+        }
+        for text in confirmations {
+            if let Err(e) = slack::post_response(&response_url, &text).await {
+                error!("Failed to confirm Slack interaction: {}", e);
+            }
+        }
+    });
+
+    StatusCode::OK
+}
+
+/// Stores the silences of the clicked buttons, returns the messages confirming them
+fn store_silences(db_url: &str, actions: Vec<Value>, user: Option<String>) -> Vec<String> {
+    let mut conn = match load_db(db_url) {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("Failed to establish a database connection: {}", e);
+            return vec![
+                ":warning: Nothing was silenced, Beebot can't reach its database".to_string(),
+            ];
+        }
+    };
+    let mut confirmations = Vec::new();
+
+    for action in actions {
+        let (Some(action_id), Some(check_name)) =
+            (action["action_id"].as_str(), action["value"].as_str())
+        else {
+            continue;
+        };
+        let Some((kind, duration)) = parse_action(action_id) else {
+            warn!("Ignoring unknown Slack action {}", action_id);
+            continue;
+        };
+
+        let silence = new_silence(check_name, kind, duration, None, user.clone());
+        let confirmation = format!("{} {}", check_name, describe(&silence));
+
+        match insert_silence(&mut conn, silence) {
+            Ok(_) => {
+                info!("{}", confirmation);
+                confirmations.push(format!(":zipper_mouth_face: {}", confirmation));
+            }
+            Err(e) => {
+                error!("Failed to store silence for {}: {:?}", check_name, e);
+                confirmations.push(format!(":warning: Failed to silence {}", check_name));
+            }
+        }
+    }
+
+    confirmations
+}
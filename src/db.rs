@@ -1,10 +1,31 @@
+use std::error::Error;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use diesel::backend::Backend;
+use diesel::migration::MigrationSource;
+use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use log::{error, info};
 
 use crate::parser::PageResults;
-use crate::schema::activity_logs;
 use crate::schema::activity_logs::dsl::*;
+use crate::schema::{
+    activity_aggregates, activity_logs, check_results, django_sessions, silences, state_labels,
+};
+use crate::validators::{ThresholdProfile, UnitValidationResult};
+
+/// Same layout as SQLite's `CURRENT_TIMESTAMP`, so stored dates compare as text
+pub const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// RFC 3339 in UTC with a fixed layout, so stored run timestamps compare as text
+pub fn timestamp(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/sqlite");
+const POSTGRES_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/postgres");
 
 #[derive(Queryable, Insertable)]
 #[diesel(table_name = activity_logs)]
@@ -18,19 +39,154 @@ pub struct LogEntry {
     pub(crate) slack_sent: bool,
     pub(crate) email_sent: bool,
     pub(crate) datetime: Option<String>,
+    pub(crate) started_at: Option<String>,
+    pub(crate) finished_at: Option<String>,
+    pub(crate) duration_ms: Option<i32>,
+    pub(crate) threshold_profile: Option<String>,
+    /// Text posted to Slack, whether or not posting succeeded
+    pub(crate) slack_message: Option<String>,
+    /// Alert email body, only set when an alert email was due
+    pub(crate) email_body: Option<String>,
 }
 
-pub fn load_db(db_url: &str) -> Result<SqliteConnection, ConnectionError> {
-    let database_url = db_url;
-    SqliteConnection::establish(&database_url)
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = check_results)]
+pub struct CheckResult {
+    pub(crate) id: Option<i32>,
+    pub(crate) log_id: i32,
+    pub(crate) name: String,
+    pub(crate) status: String,
+    pub(crate) message: Option<String>,
+    pub(crate) url: Option<String>,
+    pub(crate) value: Option<String>,
+}
+
+/// Min/max/avg and status counts of a check over an hour or a day
+#[derive(Queryable, Insertable, AsChangeset)]
+#[diesel(table_name = activity_aggregates)]
+pub struct Aggregate {
+    pub(crate) id: Option<i32>,
+    pub(crate) resolution: String,
+    pub(crate) period_start: String,
+    pub(crate) name: String,
+    pub(crate) samples: i32,
+    pub(crate) min_value: Option<i64>,
+    pub(crate) max_value: Option<i64>,
+    pub(crate) avg_value: Option<f64>,
+    pub(crate) ok_count: i32,
+    pub(crate) warning_count: i32,
+    pub(crate) alert_count: i32,
+}
+
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = silences)]
+pub struct Silence {
+    pub(crate) id: Option<i32>,
+    pub(crate) check_name: String,
+    pub(crate) kind: String,
+    pub(crate) expires_at: Option<String>,
+    pub(crate) reason: Option<String>,
+    pub(crate) created_by: Option<String>,
+    pub(crate) created_at: Option<String>,
+}
+
+/// A raw label seen in a source column, see `labels::record`
+#[derive(Queryable, Insertable, AsChangeset)]
+#[diesel(table_name = state_labels)]
+pub struct StateLabel {
+    pub(crate) id: Option<i32>,
+    pub(crate) source: String,
+    pub(crate) column_name: String,
+    pub(crate) label: String,
+    pub(crate) first_seen: String,
+    pub(crate) last_seen: String,
+    /// Rows with the label in the last run it was seen
+    pub(crate) last_count: i32,
+}
+
+/// Cookies of a Django admin login, reused by the next runs until the session expires
+#[derive(Queryable, Insertable, AsChangeset)]
+#[diesel(table_name = django_sessions)]
+pub struct DjangoSession {
+    pub(crate) login_url: String,
+    /// `Cookie` header sent to the admin, e.g. `csrftoken=...; sessionid=...`
+    pub(crate) cookies: String,
+    pub(crate) saved_at: String,
+}
+
+/// Storage backend, the same queries run on both through diesel's multi-backend support
+#[derive(diesel::MultiConnection)]
+pub enum DbConnection {
+    Sqlite(SqliteConnection),
+    Postgres(PgConnection),
+}
+
+/// Runs `$body` with the concrete connection. Inserts need it: the multi-backend can't
+/// render the `DEFAULT` values used for `None` ids and dates
+macro_rules! with_backend {
+    ($conn:expr, |$backend:ident| $body:expr) => {
+        match $conn {
+            DbConnection::Sqlite($backend) => $body,
+            DbConnection::Postgres($backend) => $body,
+        }
+    };
+}
+
+/// `postgres://` and `postgresql://` URLs use Postgres, anything else is a SQLite path
+pub fn load_db(db_url: &str) -> Result<DbConnection, ConnectionError> {
+    if db_url.starts_with("postgres://") || db_url.starts_with("postgresql://") {
+        PgConnection::establish(db_url).map(DbConnection::Postgres)
+    } else {
+        let path = db_url.strip_prefix("sqlite://").unwrap_or(db_url);
+        SqliteConnection::establish(path).map(DbConnection::Sqlite)
+    }
+}
+
+/// Applies the pending migrations, refuses databases migrated by a newer beebot
+fn apply_migrations<DB: Backend>(
+    conn: &mut impl MigrationHarness<DB>,
+    migrations: EmbeddedMigrations,
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let known: Vec<String> = MigrationSource::<DB>::migrations(&migrations)?
+        .iter()
+        .map(|migration| migration.name().version().to_string())
+        .collect();
+    let unknown: Vec<String> = conn
+        .applied_migrations()?
+        .iter()
+        .map(|version| version.to_string())
+        .filter(|version| !known.contains(version))
+        .collect();
+
+    if !unknown.is_empty() {
+        return Err(format!(
+            "database schema is newer than this beebot (unknown migrations {}), refusing to run",
+            unknown.join(", ")
+        )
+        .into());
+    }
+
+    let applied = conn.run_pending_migrations(migrations)?;
+    Ok(applied.iter().map(|version| version.to_string()).collect())
+}
+
+/// Returns the versions of the migrations applied by this call
+pub fn migrate(conn: &mut DbConnection) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    match conn {
+        DbConnection::Sqlite(conn) => apply_migrations(conn, SQLITE_MIGRATIONS),
+        DbConnection::Postgres(conn) => apply_migrations(conn, POSTGRES_MIGRATIONS),
+    }
 }
 
 pub fn create_log(
     page_results: &PageResults,
     is_slack_message_sent: bool,
     is_email_sent: bool,
+    started: DateTime<Utc>,
+    finished: DateTime<Utc>,
+    profile: ThresholdProfile,
 ) -> LogEntry {
-    let log_entry = LogEntry {
+    LogEntry {
         id: None,
         payments: page_results.validated_payments_count.validated as i32,
         vouchers: page_results.paid_vouchers_count.paid as i32,
@@ -40,22 +196,111 @@ pub fn create_log(
         slack_sent: is_slack_message_sent,
         email_sent: is_email_sent,
         datetime: None,
-    };
+        started_at: Some(timestamp(started)),
+        finished_at: Some(timestamp(finished)),
+        duration_ms: Some((finished - started).num_milliseconds() as i32),
+        threshold_profile: Some(profile.as_str().to_string()),
+        slack_message: None,
+        email_body: None,
+    }
+}
 
-    log_entry
+pub fn create_check_results(
+    run_id: i32,
+    validation_results: &[(UnitValidationResult, String)],
+) -> Vec<CheckResult> {
+    validation_results
+        .iter()
+        .map(|(result, url)| CheckResult {
+            id: None,
+            log_id: run_id,
+            name: result.name.clone(),
+            status: result.status.as_str().to_string(),
+            message: Some(result.message.clone()),
+            url: Some(url.clone()),
+            value: Some(result.value.to_string()),
+        })
+        .collect()
 }
 
-pub fn insert_log(conn: &mut SqliteConnection, log_entry: LogEntry) {
-    match diesel::insert_into(activity_logs)
+/// Returns the id of the new run, used to attach its check results
+pub fn insert_log(conn: &mut DbConnection, log_entry: LogEntry) -> Option<i32> {
+    let inserted = with_backend!(conn, |conn| diesel::insert_into(activity_logs)
         .values(&log_entry)
-        .execute(conn)
-    {
-        Ok(_) => info!("Results inserted into the database"),
-        Err(e) => error!("Failed to insert results into the database: {:?}", e),
+        .returning(id)
+        .get_result::<Option<i32>>(conn));
+
+    match inserted {
+        Ok(run_id) => {
+            info!("Results inserted into the database");
+            run_id
+        }
+        Err(e) => {
+            error!("Failed to insert results into the database: {:?}", e);
+            None
+        }
+    }
+}
+
+pub fn insert_check_results(conn: &mut DbConnection, results: Vec<CheckResult>) {
+    let inserted = with_backend!(conn, |conn| diesel::insert_into(check_results::table)
+        .values(&results)
+        .execute(conn));
+
+    match inserted {
+        Ok(_) => info!("Check results inserted into the database"),
+        Err(e) => error!("Failed to insert check results into the database: {:?}", e),
     }
 }
 
-pub fn get_last_log(conn: &mut Result<SqliteConnection, ConnectionError>) -> Option<LogEntry> {
+/// Runs started in `[from, to)` (formatted with `timestamp`), oldest first
+pub fn get_logs_between(
+    conn: &mut DbConnection,
+    from: &str,
+    to: &str,
+) -> QueryResult<Vec<LogEntry>> {
+    activity_logs
+        .filter(started_at.ge(from).and(started_at.lt(to)))
+        .order(id.asc())
+        .load(conn)
+}
+
+pub fn get_check_results_between(
+    conn: &mut DbConnection,
+    from: &str,
+    to: &str,
+) -> QueryResult<Vec<CheckResult>> {
+    check_results::table
+        .inner_join(activity_logs)
+        .filter(started_at.ge(from).and(started_at.lt(to)))
+        .select(check_results::all_columns)
+        .order(check_results::id.asc())
+        .load(conn)
+}
+
+/// Runs and their check results started in `[from, to)`
+pub fn get_history(
+    conn: &mut DbConnection,
+    from: &str,
+    to: &str,
+) -> QueryResult<(Vec<LogEntry>, Vec<CheckResult>)> {
+    let logs = get_logs_between(conn, from, to)?;
+    let results = get_check_results_between(conn, from, to)?;
+    Ok((logs, results))
+}
+
+pub fn get_log(conn: &mut DbConnection, run_id: i32) -> QueryResult<Option<LogEntry>> {
+    activity_logs.filter(id.eq(run_id)).first(conn).optional()
+}
+
+pub fn get_check_results(conn: &mut DbConnection, run_id: i32) -> QueryResult<Vec<CheckResult>> {
+    check_results::table
+        .filter(check_results::log_id.eq(run_id))
+        .order(check_results::id.asc())
+        .load(conn)
+}
+
+pub fn get_last_log(conn: &mut Result<DbConnection, ConnectionError>) -> Option<LogEntry> {
     match conn {
         Ok(conn) => match activity_logs.order(id.desc()).first(conn) {
             Ok(entry) => {
@@ -73,3 +318,152 @@ pub fn get_last_log(conn: &mut Result<SqliteConnection, ConnectionError>) -> Opt
         }
     }
 }
+
+pub fn insert_silence(conn: &mut DbConnection, silence: Silence) -> QueryResult<usize> {
+    with_backend!(conn, |conn| diesel::insert_into(silences::table)
+        .values(&silence)
+        .execute(conn))
+}
+
+pub fn get_active_silences(conn: &mut Result<DbConnection, ConnectionError>) -> Vec<Silence> {
+    let now = Utc::now().format(DATETIME_FORMAT).to_string();
+
+    match conn {
+        Ok(conn) => match silences::table
+            .filter(
+                silences::expires_at
+                    .is_null()
+                    .or(silences::expires_at.gt(now)),
+            )
+            .order(silences::id.asc())
+            .load(conn)
+        {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Error fetching active silences: {:?}", e);
+                Vec::new()
+            }
+        },
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Drops the "until resolved" silences of checks that are back to normal
+pub fn delete_resolved_silences(conn: &mut DbConnection, check_names: &[String]) {
+    match diesel::delete(
+        silences::table
+            .filter(silences::expires_at.is_null())
+            .filter(silences::check_name.eq_any(check_names)),
+    )
+    .execute(conn)
+    {
+        Ok(0) => {}
+        Ok(count) => info!("Removed {} silence(s) of resolved checks", count),
+        Err(e) => error!("Failed to remove resolved silences: {:?}", e),
+    }
+}
+
+pub fn delete_silence(conn: &mut DbConnection, silence_id: i32) -> QueryResult<usize> {
+    diesel::delete(silences::table.filter(silences::id.eq(silence_id))).execute(conn)
+}
+
+/// Deletes the runs started before `before` along with their check results
+pub fn delete_logs_before(conn: &mut DbConnection, before: &str) -> QueryResult<usize> {
+    let old_runs = activity_logs.select(id).filter(started_at.lt(before));
+    diesel::delete(check_results::table.filter(check_results::log_id.nullable().eq_any(old_runs)))
+        .execute(conn)?;
+    diesel::delete(activity_logs.filter(started_at.lt(before))).execute(conn)
+}
+
+pub fn get_aggregates_before(
+    conn: &mut DbConnection,
+    resolution: &str,
+    before: &str,
+) -> QueryResult<Vec<Aggregate>> {
+    activity_aggregates::table
+        .filter(activity_aggregates::resolution.eq(resolution))
+        .filter(activity_aggregates::period_start.lt(before))
+        .order(activity_aggregates::period_start.asc())
+        .load(conn)
+}
+
+pub fn get_aggregate(
+    conn: &mut DbConnection,
+    resolution: &str,
+    period_start: &str,
+    name: &str,
+) -> QueryResult<Option<Aggregate>> {
+    activity_aggregates::table
+        .filter(activity_aggregates::resolution.eq(resolution))
+        .filter(activity_aggregates::period_start.eq(period_start))
+        .filter(activity_aggregates::name.eq(name))
+        .first(conn)
+        .optional()
+}
+
+/// Inserts a new bucket, or overwrites the existing one when `aggregate.id` is set
+pub fn save_aggregate(conn: &mut DbConnection, aggregate: &Aggregate) -> QueryResult<usize> {
+    match aggregate.id {
+        Some(aggregate_id) => diesel::update(
+            activity_aggregates::table.filter(activity_aggregates::id.eq(aggregate_id)),
+        )
+        .set(aggregate)
+        .execute(conn),
+        None => with_backend!(conn, |conn| diesel::insert_into(activity_aggregates::table)
+            .values(aggregate)
+            .execute(conn)),
+    }
+}
+
+pub fn delete_aggregates_before(
+    conn: &mut DbConnection,
+    resolution: &str,
+    before: &str,
+) -> QueryResult<usize> {
+    diesel::delete(
+        activity_aggregates::table
+            .filter(activity_aggregates::resolution.eq(resolution))
+            .filter(activity_aggregates::period_start.lt(before)),
+    )
+    .execute(conn)
+}
+
+pub fn get_state_labels(conn: &mut DbConnection) -> QueryResult<Vec<StateLabel>> {
+    state_labels::table
+        .order((
+            state_labels::source.asc(),
+            state_labels::column_name.asc(),
+            state_labels::label.asc(),
+        ))
+        .load(conn)
+}
+
+/// Inserts a new label, or overwrites the existing one when `label.id` is set
+pub fn save_state_label(conn: &mut DbConnection, label: &StateLabel) -> QueryResult<usize> {
+    match label.id {
+        Some(label_id) => diesel::update(state_labels::table.filter(state_labels::id.eq(label_id)))
+            .set(label)
+            .execute(conn),
+        None => with_backend!(conn, |conn| diesel::insert_into(state_labels::table)
+            .values(label)
+            .execute(conn)),
+    }
+}
+
+pub fn get_django_sessions(conn: &mut DbConnection) -> QueryResult<Vec<DjangoSession>> {
+    django_sessions::table.load(conn)
+}
+
+/// Replaces the cookies stored for `session.login_url`
+pub fn save_django_session(conn: &mut DbConnection, session: &DjangoSession) -> QueryResult<usize> {
+    with_backend!(conn, |conn| diesel::insert_into(django_sessions::table)
+        .values(session)
+        .on_conflict(django_sessions::login_url)
+        .do_update()
+        .set(session)
+        .execute(conn))
+}
+
+pub fn vacuum(conn: &mut DbConnection) -> QueryResult<usize> {
+    diesel::sql_query("VACUUM").execute(conn)
+}
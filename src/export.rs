@@ -0,0 +1,142 @@
+use std::error::Error;
+use std::io::Write;
+use std::sync::Arc;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use clap::ValueEnum;
+use parquet::basic::Compression;
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DataType, Int32Type, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::schema::parser::parse_message_type;
+
+use crate::db::{timestamp, DATETIME_FORMAT};
+use crate::history::Sample;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Format {
+    Csv,
+    Json,
+    Parquet,
+}
+
+const PARQUET_SCHEMA: &str = "
+    message beebot_export {
+        REQUIRED INT32 run_id;
+        OPTIONAL BYTE_ARRAY datetime (UTF8);
+        REQUIRED BYTE_ARRAY check (UTF8);
+        OPTIONAL BYTE_ARRAY status (UTF8);
+        OPTIONAL INT64 value;
+        REQUIRED BOOLEAN slack_sent;
+        REQUIRED BOOLEAN email_sent;
+    }
+";
+
+/// Accepts `YYYY-MM-DD`, `YYYY-MM-DD HH:MM:SS` (UTC) or RFC 3339, returns a `db::timestamp`
+pub fn parse_date(value: &str) -> Result<String, String> {
+    let date = if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        date.naive_utc()
+    } else if let Ok(date) = NaiveDateTime::parse_from_str(value, DATETIME_FORMAT) {
+        date
+    } else if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        date.and_hms_opt(0, 0, 0).unwrap()
+    } else {
+        return Err(format!("invalid date `{}`", value));
+    };
+
+    Ok(timestamp(date.and_utc()))
+}
+
+pub fn write_rows(
+    rows: &[Sample],
+    format: Format,
+    output: Box<dyn Write + Send>,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        Format::Csv => write_csv(rows, output),
+        Format::Json => {
+            let mut output = output;
+            serde_json::to_writer_pretty(&mut output, rows)?;
+            writeln!(output)?;
+            Ok(())
+        }
+        Format::Parquet => write_parquet(rows, output),
+    }
+}
+
+fn write_csv(rows: &[Sample], output: Box<dyn Write + Send>) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(output);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Definition levels of an optional column: 1 when the value is present
+fn optional_column<T>(values: impl Iterator<Item = Option<T>>) -> (Vec<T>, Vec<i16>) {
+    let mut present = Vec::new();
+    let mut levels = Vec::new();
+    for value in values {
+        match value {
+            Some(value) => {
+                present.push(value);
+                levels.push(1);
+            }
+            None => levels.push(0),
+        }
+    }
+    (present, levels)
+}
+
+fn write_column<T: DataType>(
+    row_group: &mut SerializedRowGroupWriter<'_, Box<dyn Write + Send>>,
+    values: &[T::T],
+    definition_levels: Option<&[i16]>,
+) -> Result<(), ParquetError> {
+    let mut column = row_group
+        .next_column()?
+        .expect("column count matches the export schema");
+    column
+        .typed::<T>()
+        .write_batch(values, definition_levels, None)?;
+    column.close()
+}
+
+fn write_parquet(rows: &[Sample], output: Box<dyn Write + Send>) -> Result<(), Box<dyn Error>> {
+    let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+    let properties = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build(),
+    );
+    let mut writer = SerializedFileWriter::new(output, schema, properties)?;
+    let mut row_group = writer.next_row_group()?;
+    let text = |value: &str| ByteArray::from(value);
+
+    let run_ids: Vec<i32> = rows.iter().map(|r| r.run_id).collect();
+    write_column::<Int32Type>(&mut row_group, &run_ids, None)?;
+
+    let (dates, levels) = optional_column(rows.iter().map(|r| r.datetime.as_deref().map(text)));
+    write_column::<ByteArrayType>(&mut row_group, &dates, Some(&levels))?;
+
+    let checks: Vec<ByteArray> = rows.iter().map(|r| text(&r.check)).collect();
+    write_column::<ByteArrayType>(&mut row_group, &checks, None)?;
+
+    let (statuses, levels) = optional_column(rows.iter().map(|r| r.status.as_deref().map(text)));
+    write_column::<ByteArrayType>(&mut row_group, &statuses, Some(&levels))?;
+
+    let (values, levels) = optional_column(rows.iter().map(|r| r.value));
+    write_column::<Int64Type>(&mut row_group, &values, Some(&levels))?;
+
+    let slack_sent: Vec<bool> = rows.iter().map(|r| r.slack_sent).collect();
+    write_column::<BoolType>(&mut row_group, &slack_sent, None)?;
+
+    let email_sent: Vec<bool> = rows.iter().map(|r| r.email_sent).collect();
+    write_column::<BoolType>(&mut row_group, &email_sent, None)?;
+
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+use chrono::DateTime;
+
+use serde::Serialize;
+
+use crate::db::{CheckResult, LogEntry};
+use crate::validators::CHECK_NAMES;
+
+/// One metric sample: a check of a run, with the run's notification outcome
+#[derive(Serialize)]
+pub struct Sample {
+    pub(crate) run_id: i32,
+    pub(crate) datetime: Option<String>,
+    pub(crate) check: String,
+    pub(crate) status: Option<String>,
+    pub(crate) value: Option<i64>,
+    pub(crate) slack_sent: bool,
+    pub(crate) email_sent: bool,
+}
+
+fn metric_value(check_name: &str, log: &LogEntry) -> Option<i64> {
+    match check_name {
+        "Validated payments" => Some(log.payments as i64),
+        "Paid vouchers" => Some(log.vouchers as i64),
+        "PDF count" => Some(log.pdf_count as i64),
+        "Email count" => Some(log.email_count as i64),
+        "Purchase website" => Some(log.website_ok as i64),
+        _ => None,
+    }
+}
+
+/// Value stored with a check result, booleans as 0/1 like `Purchase website`
+fn stored_value(result: &CheckResult) -> Option<i64> {
+    match result.value.as_deref()? {
+        "true" => Some(1),
+        "false" => Some(0),
+        value => value.parse().ok(),
+    }
+}
+
+/// Flattens runs into samples, joining the values stored on the run with the check statuses.
+/// Checks without a column on the run, like stuck and reconcile rules, take their result's value
+pub fn build_samples(
+    logs: &[LogEntry],
+    check_results: &[CheckResult],
+    check_filter: Option<&str>,
+) -> Vec<Sample> {
+    let mut results_by_run: HashMap<i32, Vec<&CheckResult>> = HashMap::new();
+    for result in check_results {
+        results_by_run
+            .entry(result.log_id)
+            .or_default()
+            .push(result);
+    }
+
+    let mut samples = Vec::new();
+
+    for log in logs {
+        let Some(run_id) = log.id else {
+            continue;
+        };
+        let run_results = results_by_run.remove(&run_id).unwrap_or_default();
+
+        let mut check_names: Vec<&str> = CHECK_NAMES.to_vec();
+        for result in &run_results {
+            if !check_names.contains(&result.name.as_str()) {
+                check_names.push(&result.name);
+            }
+        }
+
+        for check_name in check_names {
+            if check_filter.is_some_and(|filter| filter != check_name) {
+                continue;
+            }
+
+            let result = run_results.iter().find(|result| result.name == check_name);
+            let status = result.map(|result| result.status.clone());
+            let value =
+                metric_value(check_name, log).or_else(|| result.and_then(|r| stored_value(r)));
+            if status.is_none() && value.is_none() {
+                continue;
+            }
+
+            samples.push(Sample {
+                run_id,
+                datetime: log.started_at.clone(),
+                check: check_name.to_string(),
+                status,
+                value,
+                slack_sent: log.slack_sent,
+                email_sent: log.email_sent,
+            });
+        }
+    }
+
+    samples
+}
+
+/// Human readable dump of a run, for `beebot show`
+pub fn format_run(log: &LogEntry, check_results: &[CheckResult]) -> String {
+    let unknown = || "unknown".to_string();
+    let sent = |is_sent: bool| if is_sent { "sent" } else { "not sent" };
+    let mut output = String::new();
+
+    output.push_str(&format!(
+        "Run #{}\nStarted:    {}\nFinished:   {}\n",
+        log.id.unwrap_or_default(),
+        log.started_at
+            .clone()
+            .or(log.datetime.clone())
+            .unwrap_or_else(unknown),
+        log.finished_at.clone().unwrap_or_else(unknown),
+    ));
+    if let Some(duration) = log.duration_ms {
+        output.push_str(&format!("Duration:   {}ms\n", duration));
+    }
+    if let Some(profile) = &log.threshold_profile {
+        output.push_str(&format!("Thresholds: {}\n", profile));
+    }
+    output.push_str(&format!(
+        "Slack:      {}\nEmail:      {}\n",
+        sent(log.slack_sent),
+        match (&log.email_body, log.email_sent) {
+            (None, false) => "not needed",
+            (_, is_sent) => sent(is_sent),
+        }
+    ));
+
+    output.push_str("\nChecks\n");
+    if check_results.is_empty() {
+        output.push_str("No check result recorded\n");
+    }
+    for result in check_results {
+        output.push_str(&format!("[{}] {}", result.status, result.name));
+        if let Some(value) = &result.value {
+            output.push_str(&format!(" = {}", value));
+        }
+        if let Some(message) = &result.message {
+            output.push_str(&format!(": {}", message));
+        }
+        output.push('\n');
+        if let Some(url) = result.url.as_ref().filter(|url| !url.is_empty()) {
+            output.push_str(&format!("    {}\n", url));
+        }
+    }
+
+    if let Some(message) = &log.slack_message {
+        output.push_str(&format!("\nSlack message\n{}\n", message.trim_end()));
+    }
+    if let Some(body) = &log.email_body {
+        output.push_str(&format!("\nEmail body\n{}\n", body.trim_end()));
+    }
+
+    output
+}
+
+const SPARKLINE_TICKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const SPARKLINE_MAX_WIDTH: usize = 60;
+
+fn short_date(date: &Option<String>) -> String {
+    match date {
+        Some(date) => DateTime::parse_from_rfc3339(date)
+            .map(|date| date.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|_| date.clone()),
+        None => "-".to_string(),
+    }
+}
+
+/// Pads before colouring, escape codes would throw the alignment off
+fn cell(text: &str, width: usize, status: Option<&str>, is_colored: bool) -> String {
+    let padded = format!("{:<width$}", text, width = width);
+    let color = match status {
+        Some("ok") => "32",
+        Some("warning") => "33",
+        Some("alert") => "31",
+        _ => return padded,
+    };
+    if is_colored {
+        format!("\x1b[{}m{}\x1b[0m", color, padded)
+    } else {
+        padded
+    }
+}
+
+/// One line per run, one column per check; cells show the value, or the status when there is none
+pub fn format_table(samples: &[Sample], is_colored: bool) -> String {
+    let mut runs: Vec<(i32, &Option<String>)> = Vec::new();
+    let mut check_names: Vec<&str> = Vec::new();
+    let mut cells: HashMap<(i32, &str), &Sample> = HashMap::new();
+
+    for sample in samples {
+        if runs.last().map(|(run_id, _)| *run_id) != Some(sample.run_id) {
+            runs.push((sample.run_id, &sample.datetime));
+        }
+        if !check_names.contains(&sample.check.as_str()) {
+            check_names.push(&sample.check);
+        }
+        cells.insert((sample.run_id, &sample.check), sample);
+    }
+
+    if runs.is_empty() {
+        return "No run recorded\n".to_string();
+    }
+
+    let text = |sample: Option<&&Sample>| match sample {
+        Some(sample) => match (sample.value, &sample.status) {
+            (Some(value), _) => value.to_string(),
+            (None, Some(status)) => status.clone(),
+            (None, None) => "-".to_string(),
+        },
+        None => "-".to_string(),
+    };
+    let run_width = runs
+        .iter()
+        .map(|(run_id, _)| run_id.to_string().len())
+        .max()
+        .unwrap_or_default()
+        .max(3);
+    let widths: Vec<usize> = check_names
+        .iter()
+        .map(|&check| {
+            runs.iter()
+                .map(|(run_id, _)| text(cells.get(&(*run_id, check))).len())
+                .max()
+                .unwrap_or_default()
+                .max(check.len())
+        })
+        .collect();
+
+    let mut output = format!("{:<run_width$}  {:<16}", "Run", "Started (UTC)");
+    for (check, width) in check_names.iter().zip(&widths) {
+        output.push_str(&format!("  {:<width$}", check, width = width));
+    }
+    output.push('\n');
+
+    for (run_id, date) in &runs {
+        output.push_str(&format!("{:<run_width$}  {:<16}", run_id, short_date(date)));
+        for (check, width) in check_names.iter().zip(&widths) {
+            let sample = cells.get(&(*run_id, *check));
+            let status = sample.and_then(|sample| sample.status.as_deref());
+            output.push_str("  ");
+            output.push_str(&cell(&text(sample), *width, status, is_colored));
+        }
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Averages consecutive values so the line fits in `SPARKLINE_MAX_WIDTH` characters
+fn sparkline(values: &[i64]) -> String {
+    let chunk_size = values.len().div_ceil(SPARKLINE_MAX_WIDTH).max(1);
+    let points: Vec<f64> = values
+        .chunks(chunk_size)
+        .map(|chunk| chunk.iter().sum::<i64>() as f64 / chunk.len() as f64)
+        .collect();
+    let min = points.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = points.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let last_tick = (SPARKLINE_TICKS.len() - 1) as f64;
+
+    points
+        .iter()
+        .map(|point| {
+            let level = if max > min {
+                ((point - min) / (max - min) * last_tick).round()
+            } else {
+                0.0
+            };
+            SPARKLINE_TICKS[level as usize]
+        })
+        .collect()
+}
+
+/// One sparkline per check that has values, oldest on the left
+pub fn format_charts(samples: &[Sample]) -> String {
+    let mut series: Vec<(&str, Vec<i64>)> = Vec::new();
+    for sample in samples {
+        let Some(value) = sample.value else {
+            continue;
+        };
+        match series.iter_mut().find(|(check, _)| *check == sample.check) {
+            Some((_, values)) => values.push(value),
+            None => series.push((&sample.check, vec![value])),
+        }
+    }
+
+    let name_width = series
+        .iter()
+        .map(|(check, _)| check.len())
+        .max()
+        .unwrap_or_default();
+    let mut output = String::new();
+    for (check, values) in series {
+        output.push_str(&format!(
+            "{:<name_width$}  {}  min {} max {} last {}\n",
+            check,
+            sparkline(&values),
+            values.iter().min().unwrap(),
+            values.iter().max().unwrap(),
+            values.last().unwrap()
+        ));
+    }
+
+    output
+}
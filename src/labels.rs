@@ -0,0 +1,87 @@
+use chrono::{DateTime, Utc};
+use diesel::ConnectionError;
+use log::error;
+
+use crate::db::{get_state_labels, save_state_label, timestamp, DbConnection, StateLabel};
+use crate::parser::{is_known_label, PageResults};
+
+/// A label stored for the first time by this run and unknown to the counters
+pub struct NewLabel {
+    pub(crate) source: String,
+    pub(crate) column: String,
+    pub(crate) label: String,
+    pub(crate) rows: usize,
+}
+
+fn seen_labels(metrics: &PageResults) -> impl Iterator<Item = (&str, &str, &str, usize)> {
+    metrics.labels.iter().flat_map(|(source, columns)| {
+        columns.iter().flat_map(move |(column, counts)| {
+            counts
+                .iter()
+                .map(move |(label, rows)| (source.as_str(), column.as_str(), label.as_str(), *rows))
+        })
+    })
+}
+
+fn find<'a>(
+    stored: &'a [StateLabel],
+    source: &str,
+    column: &str,
+    label: &str,
+) -> Option<&'a StateLabel> {
+    stored
+        .iter()
+        .find(|s| s.source == source && s.column_name == column && s.label == label)
+}
+
+/// None without database, the labels can't be told new then
+pub fn stored(conn: &mut Result<DbConnection, ConnectionError>) -> Option<Vec<StateLabel>> {
+    match get_state_labels(conn.as_mut().ok()?) {
+        Ok(labels) => Some(labels),
+        Err(e) => {
+            error!("Error fetching state labels: {:?}", e);
+            None
+        }
+    }
+}
+
+/// The known labels are left aside, so that a fresh database doesn't warn about all of them
+pub fn new_labels(stored: &[StateLabel], metrics: &PageResults) -> Vec<NewLabel> {
+    seen_labels(metrics)
+        .filter(|(source, column, label, _)| {
+            !is_known_label(source, column, label) && find(stored, source, column, label).is_none()
+        })
+        .map(|(source, column, label, rows)| NewLabel {
+            source: source.to_string(),
+            column: column.to_string(),
+            label: label.to_string(),
+            rows,
+        })
+        .collect()
+}
+
+/// Stores every label of the run with its row count
+pub fn record(
+    conn: &mut DbConnection,
+    stored: &[StateLabel],
+    metrics: &PageResults,
+    seen_at: DateTime<Utc>,
+) {
+    let seen_at = timestamp(seen_at);
+
+    for (source, column, label, rows) in seen_labels(metrics) {
+        let existing = find(stored, source, column, label);
+        let state_label = StateLabel {
+            id: existing.and_then(|existing| existing.id),
+            source: source.to_string(),
+            column_name: column.to_string(),
+            label: label.to_string(),
+            first_seen: existing.map_or(seen_at.clone(), |existing| existing.first_seen.clone()),
+            last_seen: seen_at.clone(),
+            last_count: rows as i32,
+        };
+        if let Err(e) = save_state_label(conn, &state_label) {
+            error!("Failed to store the `{}` label {}: {:?}", column, label, e);
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use chrono::{Local, NaiveDateTime, SecondsFormat, Utc};
+use log::kv::{Error as KvError, Key, Value as KvValue, VisitSource};
+use log::{Level, Log, Metadata, Record};
+use serde_json::{json, Map, Value as JsonValue};
+
+use crate::config::{LogFormat, LogOutput, Logging};
+
+const LOG_FILE_NAME: &str = "beebot.log";
+/// Suffix of the rotated files, and whole name of the one-file-per-run logs of older versions
+const ROTATED_DATE_FORMAT: &str = "%Y_%m_%d_%H-%M-%S";
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Identifies the current invocation in every log line, set by `set_run_id`
+static RUN_ID: Mutex<Option<String>> = Mutex::new(None);
+/// Values replaced by `[REDACTED]` in every log line, registered by `redact`
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+/// Shorter values would mangle unrelated text
+const MIN_SECRET_LENGTH: usize = 4;
+
+/// `logs/beebot.log`, renamed with a timestamp suffix once too big or too old
+struct RotatingFile {
+    directory: PathBuf,
+    file: File,
+    size: u64,
+    opened_at: SystemTime,
+    max_size: u64,
+    max_age: Duration,
+    retention: Duration,
+}
+
+enum Sink {
+    Stderr,
+    File(RotatingFile),
+    Syslog(UnixDatagram),
+    Journald(UnixDatagram),
+}
+
+struct Logger {
+    format: LogFormat,
+    sink: Mutex<Sink>,
+}
+
+/// Key-values attached to a record, e.g. `info!(check = name; "...")`
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: KvValue<'kvs>) -> Result<(), KvError> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+impl RotatingFile {
+    fn open(logging: &Logging) -> io::Result<RotatingFile> {
+        let directory = PathBuf::from(&logging.directory);
+        fs::create_dir_all(&directory)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(directory.join(LOG_FILE_NAME))?;
+        let metadata = file.metadata()?;
+        let opened_at = metadata
+            .created()
+            .or_else(|_| metadata.modified())
+            .unwrap_or_else(|_| SystemTime::now());
+
+        let rotating_file = RotatingFile {
+            directory,
+            file,
+            size: metadata.len(),
+            opened_at,
+            max_size: logging.max_size_mb * 1024 * 1024,
+            max_age: Duration::from_secs(logging.max_age_hours * 3600),
+            retention: Duration::from_secs(logging.retention_days * 24 * 3600),
+        };
+        rotating_file.delete_expired();
+        Ok(rotating_file)
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        let is_too_old = self.opened_at.elapsed().is_ok_and(|age| age > self.max_age);
+        if self.size > 0 && (self.size >= self.max_size || is_too_old) {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let current = self.directory.join(LOG_FILE_NAME);
+        let rotated = self.directory.join(format!(
+            "beebot-{}.log",
+            Local::now().format(ROTATED_DATE_FORMAT)
+        ));
+        fs::rename(&current, rotated)?;
+
+        self.file = OpenOptions::new().create(true).append(true).open(current)?;
+        self.size = 0;
+        self.opened_at = SystemTime::now();
+        self.delete_expired();
+        Ok(())
+    }
+
+    /// Also removes the one-file-per-run logs written by older versions
+    fn delete_expired(&self) {
+        let Ok(entries) = fs::read_dir(&self.directory) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_rotated_log = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(is_rotated_log);
+            let is_expired = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age > self.retention);
+
+            if is_rotated_log && is_expired {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// Only Beebot's files, the directory may be shared with other programs, e.g. `/var/log`
+fn is_rotated_log(file_name: &str) -> bool {
+    let Some(stem) = file_name.strip_suffix(".log") else {
+        return false;
+    };
+    let date = stem.strip_prefix("beebot-").unwrap_or(stem);
+    NaiveDateTime::parse_from_str(date, ROTATED_DATE_FORMAT).is_ok()
+}
+
+fn syslog_severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Native journald protocol, values with a newline use the length-prefixed binary form
+fn journald_field(datagram: &mut Vec<u8>, name: &str, value: &str) {
+    datagram.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
+
+impl Logger {
+    fn format_line(
+        &self,
+        record: &Record,
+        message: &str,
+        run_id: Option<&str>,
+        fields: &Fields,
+    ) -> String {
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+
+        match self.format {
+            LogFormat::Text => {
+                let mut line = format!("{} [{}]", timestamp, record.level());
+                if let Some(run_id) = run_id {
+                    line.push_str(&format!(" [{}]", run_id));
+                }
+                line.push_str(&format!(" {}", message));
+                for (key, value) in &fields.0 {
+                    line.push_str(&format!(" {}={}", key, value));
+                }
+                line
+            }
+            LogFormat::Json => {
+                let mut object = Map::new();
+                object.insert("timestamp".to_string(), json!(timestamp));
+                object.insert("level".to_string(), json!(record.level().as_str()));
+                object.insert("target".to_string(), json!(record.target()));
+                object.insert("run_id".to_string(), json!(run_id));
+                object.insert("message".to_string(), json!(message));
+                for (key, value) in &fields.0 {
+                    object.insert(key.clone(), json!(value));
+                }
+                JsonValue::Object(object).to_string()
+            }
+        }
+    }
+
+    fn write(&self, record: &Record, line: &str, run_id: Option<&str>, fields: &Fields) {
+        let mut sink = self.sink.lock().unwrap_or_else(|e| e.into_inner());
+
+        let result = match &mut *sink {
+            Sink::Stderr => writeln!(io::stderr(), "{}", line),
+            Sink::File(file) => file.write(line),
+            Sink::Syslog(socket) => {
+                // Facility "user"
+                let priority = 8 + syslog_severity(record.level());
+                let message = format!(
+                    "<{}>{} beebot[{}]: {}",
+                    priority,
+                    Local::now().format("%b %e %H:%M:%S"),
+                    std::process::id(),
+                    line
+                );
+                socket.send(message.as_bytes()).map(|_| ())
+            }
+            Sink::Journald(socket) => {
+                let mut datagram = Vec::new();
+                journald_field(&mut datagram, "MESSAGE", line);
+                journald_field(
+                    &mut datagram,
+                    "PRIORITY",
+                    &syslog_severity(record.level()).to_string(),
+                );
+                journald_field(&mut datagram, "SYSLOG_IDENTIFIER", "beebot");
+                if let Some(run_id) = run_id {
+                    journald_field(&mut datagram, "BEEBOT_RUN_ID", run_id);
+                }
+                for (key, value) in &fields.0 {
+                    let name = format!("BEEBOT_{}", key.to_uppercase());
+                    journald_field(&mut datagram, &name, value);
+                }
+                socket.send(&datagram).map(|_| ())
+            }
+        };
+
+        if let Err(e) = result {
+            eprintln!("Failed to write log ({}): {}", e, line);
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Before formatting, JSON escaping would hide secrets with quotes or backslashes
+        let mut fields = Fields(Vec::new());
+        let _ = record.key_values().visit(&mut fields);
+        for (_, value) in fields.0.iter_mut() {
+            *value = redacted(value);
+        }
+        let message = redacted(&record.args().to_string());
+        let run_id = RUN_ID.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let line = self.format_line(record, &message, run_id.as_deref(), &fields);
+        self.write(record, &line, run_id.as_deref(), &fields);
+    }
+
+    fn flush(&self) {
+        if let Sink::File(file) = &mut *self.sink.lock().unwrap_or_else(|e| e.into_inner()) {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+fn redacted(text: &str) -> String {
+    let mut text = text.to_string();
+    for secret in SECRETS.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        text = text.replace(secret.as_str(), "[REDACTED]");
+    }
+    text
+}
+
+fn connect(path: &str) -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(path)?;
+    Ok(socket)
+}
+
+/// Falls back to stderr when the configured output can't be opened
+pub fn init(logging: &Logging) {
+    let sink = match logging.output {
+        LogOutput::Stderr => Ok(Sink::Stderr),
+        LogOutput::File => RotatingFile::open(logging).map(Sink::File),
+        LogOutput::Syslog => connect(SYSLOG_SOCKET).map(Sink::Syslog),
+        LogOutput::Journald => connect(JOURNALD_SOCKET).map(Sink::Journald),
+    };
+    let sink = sink.unwrap_or_else(|e| {
+        eprintln!("Failed to open the log output, logging to stderr: {}", e);
+        Sink::Stderr
+    });
+
+    let logger = Logger {
+        format: logging.format,
+        sink: Mutex::new(sink),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(logging.level);
+    }
+}
+
+/// Tags the following log lines, e.g. with the run start timestamp stored in `activity_logs`
+pub fn set_run_id(run_id: &str) {
+    *RUN_ID.lock().unwrap_or_else(|e| e.into_inner()) = Some(run_id.to_string());
+}
+
+pub fn redact(secrets: &[&str]) {
+    let mut registered = SECRETS.lock().unwrap_or_else(|e| e.into_inner());
+    for secret in secrets {
+        if secret.len() >= MIN_SECRET_LENGTH && !registered.iter().any(|s| s == secret) {
+            registered.push(secret.to_string());
+        }
+    }
+    // Longest first, so a secret containing another one is fully replaced
+    registered.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+}
@@ -2,11 +2,15 @@ use std::string::String;
 
 use serde_json::json;
 
+use crate::db::Silence;
+use crate::silence::{describe, find_silence};
 use crate::validators::{Status, UnitValidationResult};
 
 pub fn compose_mail_body(
     validation_results: &Vec<(UnitValidationResult, String)>,
+    silences: &[Silence],
     is_test_mode: bool,
+    drill_down_rows: usize,
 ) -> String {
     let mut message = "".to_string();
 
@@ -21,29 +25,59 @@ pub fn compose_mail_body(
             Status::Alert => "❌",
         };
         let clean_message = result.message.replace('`', "");
+        let silence_note = match find_silence(&result.name, silences) {
+            Some(silence) if result.status != Status::Ok => format!(" ({})", describe(silence)),
+            _ => String::new(),
+        };
         message.push_str(&format!(
-            "{} {}: {}\n",
-            status_text, result.name, clean_message,
+            "{} {}: {}{}\n",
+            status_text, result.name, clean_message, silence_note,
         ));
+
+        if result.status == Status::Ok {
+            continue;
+        }
+        for item in result.failing_items.iter().take(drill_down_rows) {
+            let mut line = format!(
+                "    - {} {}",
+                item.id.as_deref().unwrap_or("row"),
+                item.state
+            );
+            for detail in &item.details {
+                line.push_str(&format!(" {}", detail));
+            }
+            if let Some(url) = &item.url {
+                line.push_str(&format!(" {}", url));
+            }
+            message.push_str(&line);
+            message.push('\n');
+        }
+        let remaining = result.failing_items.len().saturating_sub(drill_down_rows);
+        if remaining > 0 {
+            message.push_str(&format!("    and {} more\n", remaining));
+        }
     }
 
     message
 }
 
-pub async fn send_mail(
-    token: &str,
-    sender: &str,
-    recipients: Vec<&String>,
-    body: &str,
-    is_test_mode: bool,
-) -> Result<(), reqwest::Error> {
+pub fn alert_subject(is_test_mode: bool) -> String {
     let test_subject = if is_test_mode {
         "THIS IS A TEST - "
     } else {
         ""
     }
     .to_string();
-    let subject = format!("🚨 {} EMERGENCY | Issue with app", test_subject);
+    format!("🚨 {} EMERGENCY | Issue with app", test_subject)
+}
+
+pub async fn send_mail(
+    token: &str,
+    sender: &str,
+    recipients: Vec<&String>,
+    subject: &str,
+    body: &str,
+) -> Result<(), reqwest::Error> {
     let client = reqwest::Client::new();
 
     let mut json_recipients = Vec::new();
@@ -1,48 +1,317 @@
-use clap::Parser;
+use std::fs::File;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::Utc;
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
 use log::{error, info};
-use tokio;
 
 extern crate diesel;
 
-use crate::db::{get_last_log, load_db, LogEntry};
+use crate::config::{load_config, Config};
+use crate::db::{get_active_silences, get_last_log, load_db, LogEntry};
+use crate::export::Format;
 use crate::mail::send_mail;
-use crate::utils::{load_environment, load_logfile};
-use crate::validators::Status;
+use crate::output::{Output, RunSummary};
+use crate::report::Period;
+use crate::silence::{is_silenced, maintenance_silences};
+use crate::utils::{load_environment, Environment};
+use crate::validators::{Status, ThresholdProfile, META_CHECKS};
 
+mod auth;
+mod config;
+mod daemon;
 mod db;
+mod export;
+mod history;
+mod labels;
+mod logging;
 mod mail;
+mod output;
 mod parser;
+mod plugin;
+mod report;
 mod requests;
+mod retention;
 mod schema;
+mod secrets;
+mod silence;
 mod slack;
+mod sql;
 mod utils;
 mod validators;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     test: bool,
+
+    /// Fetch and validate real data, but print notifications instead of sending them and don't record the run
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Fetch, validate and notify once (default)
+    Run {
+        /// With `json`, the exit code is 0 when all checks are ok, 1 on warnings, 2 on alerts
+        #[arg(long, value_enum, default_value_t = Output::Text)]
+        output: Output,
+    },
+    /// Run a single check as an Icinga/Nagios plugin: exit 0/1/2/3 for OK/WARNING/CRITICAL/UNKNOWN
+    Check {
+        /// Check name, e.g. "PDF count" or pdf_count
+        name: String,
+    },
+    /// Serve the Slack interactions endpoint (Ack / Silence buttons)
+    Daemon,
+    /// Summarize past runs and send the digest to Slack and by email
+    ///
+    /// Only raw runs are summarized, not the aggregates `db maintain` rolls them into
+    Report {
+        #[arg(long, value_enum, default_value_t = Period::Daily)]
+        period: Period,
+    },
+    /// Dump run history and metric samples
+    ///
+    /// Only raw runs are exported: once `db maintain` rolls runs older than `retention.raw_days`
+    /// into aggregates, they can't be exported anymore
+    Export {
+        /// Start date, inclusive (YYYY-MM-DD, YYYY-MM-DD HH:MM:SS in UTC, or RFC 3339)
+        #[arg(long, value_parser = export::parse_date)]
+        from: Option<String>,
+        /// End date, exclusive, now by default
+        #[arg(long, value_parser = export::parse_date)]
+        to: Option<String>,
+        #[arg(long, value_enum, default_value_t = Format::Csv)]
+        format: Format,
+        /// Only export samples of this check
+        #[arg(long)]
+        check: Option<String>,
+        /// Output file, stdout by default
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Print past runs, one column per check
+    ///
+    /// Only raw runs are listed, see `retention.raw_days`
+    History {
+        /// Only show this check
+        #[arg(long)]
+        check: Option<String>,
+        /// How far back to look, e.g. 24h, 7days
+        #[arg(long, value_parser = humantime::parse_duration, default_value = "24h")]
+        since: Duration,
+        /// Also draw a sparkline per metric
+        #[arg(long)]
+        chart: bool,
+    },
+    /// Show what a run measured and the messages it sent
+    Show { run_id: i32 },
+    /// Database administration
+    Db {
+        #[command(subcommand)]
+        action: DbCommand,
+    },
+    /// Manage silences: checks still run but don't ping the channel nor send emails
+    Silence {
+        #[command(subcommand)]
+        action: SilenceCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum DbCommand {
+    /// Apply the pending schema migrations, also done automatically at startup
+    Migrate,
+    /// Apply the retention policy (roll old runs into aggregates) and VACUUM
+    ///
+    /// The rolled up runs are deleted: `report`, `history`, `export` and `show` no longer see them
+    Maintain,
+}
+
+#[derive(Subcommand, Debug)]
+enum SilenceCommand {
+    /// Silence a check for a duration, or until it is resolved
+    Add {
+        #[arg(long)]
+        check: String,
+        /// e.g. 30m, 2h, 1day
+        #[arg(long = "for", value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// List active silences, including maintenance windows
+    List,
+    /// Remove a silence by id
+    Remove { id: i32 },
 }
 
 #[tokio::main]
 async fn main() {
     // Get environment variables
     dotenv().ok();
-    let env = load_environment();
+    let env = load_environment().await;
+
+    // Get arguments from CLI
+    let args = Args::parse();
+
+    let command = args.command.unwrap_or(Command::Run {
+        output: Output::Text,
+    });
+    let is_plugin = matches!(command, Command::Check { .. });
+
+    let loaded_config = match load_config(&env.config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            // Logging isn't set up without config
+            if is_plugin {
+                println!("BEEBOT UNKNOWN - {}", e);
+                std::process::exit(plugin::UNKNOWN);
+            }
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let has_config_file = loaded_config.is_some();
+    let config = loaded_config.unwrap_or_default();
 
     // Init logging
-    load_logfile().expect("Failed to initialize logger");
+    logging::init(&config.logging);
     info!("Beebot starting");
+    if !has_config_file {
+        info!("No config file at {}, using defaults", env.config_path);
+    }
+
+    let is_migrate_command = matches!(
+        command,
+        Command::Db {
+            action: DbCommand::Migrate
+        }
+    );
+    // A dry run leaves the database as it is, schema included
+    if args.dry_run {
+        info!("Dry run, database migrations not applied");
+        if is_migrate_command {
+            println!("Dry run, database migrations not applied");
+        }
+    } else {
+        migrate_database(&env.db_url, is_migrate_command, is_plugin);
+    }
+
+    let mut exit_code = 0;
+    match command {
+        Command::Run { output } => {
+            exit_code = run(&env, &config, args.test, args.dry_run, output).await
+        }
+        Command::Check { name } => {
+            exit_code = run_check(&env, &config, &name, args.test, args.dry_run).await
+        }
+        Command::Daemon => daemon::serve(&env, &config).await,
+        Command::Report { period } => send_report(&env, period, args.test, args.dry_run).await,
+        Command::Export {
+            from,
+            to,
+            format,
+            check,
+            out,
+        } => export_history(&env, from, to, format, check, out),
+        Command::History {
+            check,
+            since,
+            chart,
+        } => print_history(&env, check, since, chart),
+        Command::Show { run_id } => show_run(&env, run_id),
+        Command::Db {
+            action: DbCommand::Maintain,
+        } => retention::run_maintenance(&env.db_url, config.retention.as_ref()),
+        Command::Db {
+            action: DbCommand::Migrate,
+        } => {}
+        Command::Silence { action } => manage_silences(&env, &config, action),
+    }
+
+    info!("Beebot shutdown");
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+}
+
+/// Brings the schema up to date, exits rather than running against a schema it doesn't know.
+/// Plugins exit UNKNOWN with a status line, as monitoring expects
+fn migrate_database(db_url: &str, is_verbose: bool, is_plugin: bool) {
+    let mut conn = match load_db(db_url) {
+        Ok(conn) => conn,
+        Err(e) => {
+            // Commands that can work without a database keep going, as before
+            error!("Failed to establish a database connection: {}", e);
+            if is_verbose {
+                eprintln!("Failed to establish a database connection: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+    };
+
+    match db::migrate(&mut conn) {
+        Ok(applied) if applied.is_empty() => {
+            if is_verbose {
+                println!("Database schema is up to date");
+            }
+        }
+        Ok(applied) => {
+            let message = format!("Applied database migrations: {}", applied.join(", "));
+            if is_verbose {
+                println!("{}", message);
+            }
+            info!("{}", message);
+        }
+        Err(e) => {
+            error!("Database migration failed: {}", e);
+            if is_plugin {
+                println!("BEEBOT UNKNOWN - database migration failed: {}", e);
+                std::process::exit(plugin::UNKNOWN);
+            }
+            eprintln!("Database migration failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Returns the process exit code
+async fn run(
+    env: &Environment,
+    config: &Config,
+    is_test_mode: bool,
+    is_dry_run: bool,
+    output: Output,
+) -> i32 {
+    // Stdout is reserved for the JSON document in JSON mode
+    let print = |text: &str| match output {
+        Output::Text => println!("{}", text),
+        Output::Json => eprintln!("{}", text),
+    };
 
-    // Get arguments from CLI
-    let args = Args::parse();
-    let is_test_mode = args.test;
     if is_test_mode {
-        println!("Running in TEST MODE");
+        print("Running in TEST MODE");
         info!("Running in TEST MODE");
     }
+    if is_dry_run {
+        print("Running in DRY RUN mode, nothing will be sent nor recorded");
+        info!("Running in DRY RUN mode");
+    }
+    let started_at = Utc::now();
+    let profile = ThresholdProfile::at(started_at);
+    logging::set_run_id(&db::timestamp(started_at));
 
     // Init database
     info!("Connecting to db");
@@ -50,26 +319,68 @@ async fn main() {
 
     // Fetch + Parse
     info!("Fetching pages content");
-    let pages = requests::request_pages(
-        &env.api_token,
-        &env.urls,
-        &env.celery_username,
-        &env.celery_password,
-        is_test_mode,
-    )
-    .await;
-    let metrics = parser::extract_metrics(&pages, is_test_mode);
+    let saved_sessions = requests::saved_sessions(&mut conn);
+    let fetched = requests::request_pages(&env.urls, config, &saved_sessions, is_test_mode).await;
+    let metrics = parser::extract_metrics(&fetched.pages, config, is_test_mode);
 
     // Metrics validation
     info!("Validating data from HTML content");
-    let results = validators::validate(&metrics);
+    let stored_labels = labels::stored(&mut conn);
+    let new_labels = stored_labels
+        .as_deref()
+        .map_or_else(Vec::new, |stored| labels::new_labels(stored, &metrics));
+    let results = validators::validate(
+        config,
+        &metrics,
+        profile,
+        &fetched.invalid_credentials,
+        &new_labels,
+    );
+    for (result, _) in &results {
+        info!(check = result.name.as_str(), status = result.status.as_str(); "{}", result.message);
+    }
     let last_log: Option<LogEntry> = get_last_log(&mut conn);
 
+    // Lift "until resolved" silences of recovered checks, then load the active ones
+    if !is_test_mode && !is_dry_run {
+        if let Ok(ref mut conn) = conn {
+            let resolved_checks: Vec<String> = results
+                .iter()
+                .filter(|(result, _)| result.status == Status::Ok)
+                .map(|(result, _)| result.name.clone())
+                .collect();
+            db::delete_resolved_silences(conn, &resolved_checks);
+        }
+    }
+    let mut silences = get_active_silences(&mut conn);
+    silences.extend(maintenance_silences(config, Utc::now()));
+
     // Generate and send Slack message
-    let slack_message = slack::create_message(&results, last_log, is_test_mode);
+    let slack_message = slack::create_message(
+        &results,
+        last_log,
+        &silences,
+        is_test_mode,
+        config.drill_down_rows,
+    );
+    let slack_blocks = slack::create_blocks(&results, &slack_message, &silences);
     info!("Sending Slack message:\n{}\n", slack_message);
-    let is_slack_message_sent =
-        match slack::post_message(&env.slack_token, &env.slack_channel, &slack_message).await {
+    let is_slack_message_sent = if is_dry_run {
+        let payload = slack::message_payload(&env.slack_channel, &slack_message, &slack_blocks);
+        print(&format!(
+            "Slack payload (not sent):\n{}\n",
+            serde_json::to_string_pretty(&payload).unwrap()
+        ));
+        false
+    } else {
+        match slack::post_message(
+            &env.slack_token,
+            &env.slack_channel,
+            &slack_message,
+            &slack_blocks,
+        )
+        .await
+        {
             Ok(_) => {
                 info!("Slack message sent");
                 true
@@ -78,31 +389,39 @@ async fn main() {
                 info!("Failed to send message to Slack: {}", e);
                 false
             }
-        };
+        }
+    };
 
     // Conditionally generate and send email
-    let mail_body = mail::compose_mail_body(&results, is_test_mode);
+    let mail_body =
+        mail::compose_mail_body(&results, &silences, is_test_mode, config.drill_down_rows);
     info!("\n{}", mail_body);
     let mut is_email_sent = false;
 
     let needs_alert = results
         .iter()
-        .any(|(result, _)| result.status == Status::Alert);
+        .any(|(result, _)| result.status == Status::Alert && !is_silenced(&result.name, &silences));
 
-    if needs_alert {
+    if needs_alert && is_dry_run {
+        print(&format!(
+            "Email (not sent)\nTo: {}\nSubject: {}\n\n{}",
+            env.mail_recipients()
+                .iter()
+                .map(|recipient| recipient.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            mail::alert_subject(is_test_mode),
+            mail_body
+        ));
+    } else if needs_alert {
         info!("Sending alert email\nMail content:\n{}", mail_body);
 
-        let recipients = vec![
-            &env.mail_recipient_1,
-            &env.mail_recipient_2,
-            &env.mail_recipient_3,
-        ];
         match send_mail(
             &env.mail_token,
             &env.mail_sender,
-            recipients,
+            env.mail_recipients(),
+            &mail::alert_subject(is_test_mode),
             &mail_body,
-            is_test_mode,
         )
         .await
         {
@@ -117,11 +436,26 @@ async fn main() {
     }
 
     // Save result in database
-    if !is_test_mode {
+    if !is_test_mode && !is_dry_run {
         match conn {
             Ok(ref mut conn) => {
-                let log_entry = db::create_log(&metrics, is_slack_message_sent, is_email_sent);
-                db::insert_log(conn, log_entry);
+                let mut log_entry = db::create_log(
+                    &metrics,
+                    is_slack_message_sent,
+                    is_email_sent,
+                    started_at,
+                    Utc::now(),
+                    profile,
+                );
+                log_entry.slack_message = Some(slack_message);
+                log_entry.email_body = needs_alert.then_some(mail_body);
+                if let Some(run_id) = db::insert_log(conn, log_entry) {
+                    db::insert_check_results(conn, db::create_check_results(run_id, &results));
+                }
+                if let Some(stored) = &stored_labels {
+                    labels::record(conn, stored, &metrics, started_at);
+                }
+                requests::save_sessions(conn, &fetched.sessions);
             }
             Err(_) => {
                 error!("Failed to establish a database connection");
@@ -129,5 +463,277 @@ async fn main() {
         }
     }
 
-    info!("Beebot shutdown");
+    match output {
+        Output::Text => 0,
+        Output::Json => {
+            let summary = RunSummary {
+                started_at,
+                profile,
+                is_test_mode,
+                urls: &env.urls,
+                pages: &fetched.pages,
+                metrics: &metrics,
+                results: &results,
+                silences: &silences,
+                slack_sent: is_slack_message_sent,
+                email_sent: is_email_sent,
+            };
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&output::run_json(&summary)).unwrap()
+            );
+            output::exit_code(&output::worst_status(&results))
+        }
+    }
+}
+
+/// Plugin mode: nothing is sent nor recorded, only the plugin line is printed
+async fn run_check(
+    env: &Environment,
+    config: &Config,
+    name: &str,
+    is_test_mode: bool,
+    is_dry_run: bool,
+) -> i32 {
+    let check_names = config.check_names();
+    let Some(check_name) = check_names
+        .iter()
+        .find(|check| plugin::matches(check, name))
+    else {
+        println!(
+            "BEEBOT UNKNOWN - no check named {}, expected one of: {}",
+            name,
+            check_names.join(", ")
+        );
+        return plugin::UNKNOWN;
+    };
+
+    let mut conn = load_db(&env.db_url);
+    let saved_sessions = requests::saved_sessions(&mut conn);
+    let fetched = requests::request_pages(&env.urls, config, &saved_sessions, is_test_mode).await;
+    if let (Ok(conn), false) = (conn.as_mut(), is_dry_run) {
+        requests::save_sessions(conn, &fetched.sessions);
+    }
+    let metrics = parser::extract_metrics(&fetched.pages, config, is_test_mode);
+    // Read only, the labels are recorded by the runs
+    let new_labels = labels::stored(&mut conn)
+        .map_or_else(Vec::new, |stored| labels::new_labels(&stored, &metrics));
+    let results = validators::validate(
+        config,
+        &metrics,
+        ThresholdProfile::at(Utc::now()),
+        &fetched.invalid_credentials,
+        &new_labels,
+    );
+
+    if validators::skipped_checks(config, &fetched.invalid_credentials).contains(check_name) {
+        println!(
+            "BEEBOT UNKNOWN - {}: Beebot credentials invalid for its source",
+            check_name
+        );
+        return plugin::UNKNOWN;
+    }
+    if validators::broken_checks(config, &metrics.broken_sources).contains(check_name) {
+        println!(
+            "BEEBOT UNKNOWN - {}: Beebot scraper broken for its source",
+            check_name
+        );
+        return plugin::UNKNOWN;
+    }
+
+    match results
+        .iter()
+        .find(|(result, _)| result.name == *check_name)
+    {
+        // Without a URL the page wasn't fetched, the values are defaults rather than measurements
+        Some((result, url)) if url.is_empty() && !META_CHECKS.contains(&result.name.as_str()) => {
+            println!("BEEBOT UNKNOWN - {}: page could not be fetched", check_name);
+            plugin::UNKNOWN
+        }
+        Some((result, _)) => {
+            let (line, code) = plugin::check_output(result);
+            info!(check = check_name; "{}", line);
+            println!("{}", line);
+            code
+        }
+        None => {
+            println!("BEEBOT UNKNOWN - {}: no result", check_name);
+            plugin::UNKNOWN
+        }
+    }
+}
+
+async fn send_report(env: &Environment, period: Period, is_test_mode: bool, is_dry_run: bool) {
+    let mut conn = match load_db(&env.db_url) {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("Failed to establish a database connection: {}", e);
+            return;
+        }
+    };
+
+    let now = Utc::now();
+    let since = db::timestamp(now - period.duration());
+    let until = db::timestamp(now);
+    let (logs, check_results) = match db::get_history(&mut conn, &since, &until) {
+        Ok(history) => history,
+        Err(e) => {
+            error!("Failed to load history from the database: {:?}", e);
+            return;
+        }
+    };
+
+    let report = report::build_report(period, now, &logs, &check_results);
+    let message = report::format_report(&report, is_test_mode);
+    info!("Sending report:\n{}\n", message);
+
+    if is_dry_run {
+        println!("Report (not sent):\n{}", message);
+        return;
+    }
+
+    let blocks = serde_json::Value::Array(slack::section_blocks(&message));
+    match slack::post_message(&env.slack_token, &env.slack_channel, &message, &blocks).await {
+        Ok(_) => info!("Slack report sent"),
+        Err(e) => error!("Failed to send report to Slack: {}", e),
+    }
+
+    match send_mail(
+        &env.mail_token,
+        &env.mail_sender,
+        env.mail_recipients(),
+        &report::report_subject(period, is_test_mode),
+        &message.replace(['`', '*'], ""),
+    )
+    .await
+    {
+        Ok(_) => info!("Report email sent"),
+        Err(e) => error!("Failed to send report email: {}", e),
+    }
+}
+
+fn export_history(
+    env: &Environment,
+    from: Option<String>,
+    to: Option<String>,
+    format: Format,
+    check: Option<String>,
+    out: Option<PathBuf>,
+) {
+    let mut conn = match load_db(&env.db_url) {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to establish a database connection: {}", e);
+            return;
+        }
+    };
+
+    let from = from.unwrap_or_default();
+    let to = to.unwrap_or_else(|| db::timestamp(Utc::now()));
+    let (logs, check_results) = match db::get_history(&mut conn, &from, &to) {
+        Ok(history) => history,
+        Err(e) => {
+            eprintln!("Failed to load history from the database: {:?}", e);
+            return;
+        }
+    };
+    let rows = history::build_samples(&logs, &check_results, check.as_deref());
+
+    let output: Box<dyn Write + Send> = match &out {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("Failed to create {}: {}", path.display(), e);
+                return;
+            }
+        },
+        None => Box::new(io::stdout()),
+    };
+
+    match export::write_rows(&rows, format, output) {
+        Ok(_) => info!("Exported {} samples from {} runs", rows.len(), logs.len()),
+        Err(e) => eprintln!("Failed to export history: {}", e),
+    }
+}
+
+fn print_history(env: &Environment, check: Option<String>, since: Duration, chart: bool) {
+    let mut conn = match load_db(&env.db_url) {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to establish a database connection: {}", e);
+            return;
+        }
+    };
+
+    let now = Utc::now();
+    let Some(from) = chrono::Duration::from_std(since)
+        .ok()
+        .and_then(|since| now.checked_sub_signed(since))
+    else {
+        eprintln!("--since is too large");
+        return;
+    };
+    let from = db::timestamp(from);
+    let (logs, check_results) = match db::get_history(&mut conn, &from, &db::timestamp(now)) {
+        Ok(history) => history,
+        Err(e) => {
+            eprintln!("Failed to load history from the database: {:?}", e);
+            return;
+        }
+    };
+    let samples = history::build_samples(&logs, &check_results, check.as_deref());
+
+    let is_colored = io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    print!("{}", history::format_table(&samples, is_colored));
+    if chart && !samples.is_empty() {
+        println!();
+        print!("{}", history::format_charts(&samples));
+    }
+}
+
+fn show_run(env: &Environment, run_id: i32) {
+    let mut conn = match load_db(&env.db_url) {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Failed to establish a database connection: {}", e);
+            return;
+        }
+    };
+
+    let run = db::get_log(&mut conn, run_id).and_then(|log| {
+        let results = db::get_check_results(&mut conn, run_id)?;
+        Ok(log.map(|log| (log, results)))
+    });
+
+    match run {
+        Ok(Some((log, results))) => print!("{}", history::format_run(&log, &results)),
+        Ok(None) => eprintln!("No run with id {}", run_id),
+        Err(e) => eprintln!("Failed to load run {}: {:?}", run_id, e),
+    }
+}
+
+fn manage_silences(env: &Environment, config: &Config, action: SilenceCommand) {
+    let mut conn = load_db(&env.db_url);
+
+    if let SilenceCommand::List = action {
+        let mut silences = get_active_silences(&mut conn);
+        silences.extend(maintenance_silences(config, Utc::now()));
+        silence::list(&silences);
+        return;
+    }
+
+    let Ok(ref mut conn) = conn else {
+        println!("Failed to establish a database connection");
+        return;
+    };
+
+    match action {
+        SilenceCommand::Add {
+            check,
+            duration,
+            reason,
+        } => silence::add(conn, &config.check_names(), &check, duration, reason),
+        SilenceCommand::Remove { id } => silence::remove(conn, id),
+        SilenceCommand::List => {}
+    }
 }
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde_json::{json, Value as JsonValue};
+
+use crate::db::{timestamp, Silence};
+use crate::parser::PageResults;
+use crate::requests::Page;
+use crate::silence::is_silenced;
+use crate::validators::{Status, ThresholdProfile, UnitValidationResult, Value};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+    /// Only the log file and the notifications
+    Text,
+    /// Print the run as JSON on stdout
+    Json,
+}
+
+pub struct RunSummary<'a> {
+    pub(crate) started_at: DateTime<Utc>,
+    pub(crate) profile: ThresholdProfile,
+    pub(crate) is_test_mode: bool,
+    pub(crate) urls: &'a [(&'static str, String)],
+    pub(crate) pages: &'a HashMap<String, Page>,
+    pub(crate) metrics: &'a PageResults,
+    pub(crate) results: &'a [(UnitValidationResult, String)],
+    pub(crate) silences: &'a [Silence],
+    pub(crate) slack_sent: bool,
+    pub(crate) email_sent: bool,
+}
+
+pub fn worst_status(results: &[(UnitValidationResult, String)]) -> Status {
+    let statuses = results.iter().map(|(result, _)| &result.status);
+    if statuses.clone().any(|status| *status == Status::Alert) {
+        Status::Alert
+    } else if statuses.clone().any(|status| *status == Status::Warning) {
+        Status::Warning
+    } else {
+        Status::Ok
+    }
+}
+
+/// 0 when everything is ok, 1 on warnings, 2 on alerts
+pub fn exit_code(status: &Status) -> i32 {
+    match status {
+        Status::Ok => 0,
+        Status::Warning => 1,
+        Status::Alert => 2,
+    }
+}
+
+fn value_json(value: &Value) -> JsonValue {
+    match value {
+        Value::Count(count) => json!(count),
+        Value::Bool(b) => json!(b),
+    }
+}
+
+pub fn run_json(summary: &RunSummary) -> JsonValue {
+    let pages: Vec<JsonValue> = summary
+        .urls
+        .iter()
+        .map(|(key, url)| match summary.pages.get(*key) {
+            Some(page) => json!({
+                "key": key,
+                "url": page.url,
+                "fetched": true,
+                "status": page.status,
+                "bytes": page.body.len(),
+                "elapsed_ms": page.elapsed_ms,
+            }),
+            None => json!({
+                "key": key,
+                "url": url,
+                "fetched": false,
+            }),
+        })
+        .collect();
+
+    let results: Vec<JsonValue> = summary
+        .results
+        .iter()
+        .map(|(result, url)| {
+            json!({
+                "name": result.name,
+                "status": result.status.as_str(),
+                "value": value_json(&result.value),
+                "message": result.message,
+                "url": url,
+                "silenced": is_silenced(&result.name, summary.silences),
+                "failing_items": result.failing_items,
+            })
+        })
+        .collect();
+
+    json!({
+        "started_at": timestamp(summary.started_at),
+        "finished_at": timestamp(Utc::now()),
+        "threshold_profile": summary.profile.as_str(),
+        "test": summary.is_test_mode,
+        "status": worst_status(summary.results).as_str(),
+        "pages": pages,
+        "metrics": summary.metrics,
+        "results": results,
+        "slack_sent": summary.slack_sent,
+        "email_sent": summary.email_sent,
+    })
+}
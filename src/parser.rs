@@ -1,17 +1,23 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Europe::Paris;
+use log::error;
 use scraper::{Html, Selector};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
 
+use crate::config::{Config, ReconcileRule, RowSet, Source, SourceFormat, StuckRule, Window};
 use crate::requests::Page;
 
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Copy, Clone, Serialize)]
 pub struct EmailStatuses {
     pub(crate) sent: usize,
     pub(crate) not_sent: usize,
     pub(crate) bulk: usize,
 }
 
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Copy, Clone, Serialize)]
 pub struct PaymentStatuses {
     pub(crate) validated: usize,
     pub(crate) to_validate: usize,
@@ -19,28 +25,34 @@ pub struct PaymentStatuses {
     pub(crate) cancelled: usize,
     pub(crate) error: usize,
     pub(crate) group: usize,
+    /// Payments in a state the counters don't know, left out of the ratio
+    pub(crate) unknown: usize,
 }
 
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Copy, Clone, Serialize)]
 pub struct VoucherStatuses {
     pub(crate) paid: usize,
     pub(crate) error: usize,
     pub(crate) other: usize,
 }
 
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Copy, Clone, Serialize)]
 pub struct PaymentTypes {
     pub(crate) individual: usize,
     pub(crate) group: usize,
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize)]
 pub struct PageResults {
     pub(crate) validated_payments_count: PaymentStatuses,
     pub(crate) payment_types_count: PaymentTypes,
     pub(crate) paid_vouchers_count: VoucherStatuses,
     pub(crate) not_imported_count: usize,
+    /// Not imported paid vouchers with an unknown `has_pdf` label, left out of the PDF ratio
+    pub(crate) unknown_pdf_count: usize,
     pub(crate) pdf_count: usize,
+    /// Likewise for `_has_been_sent` and the email ratio
+    pub(crate) unknown_email_count: usize,
     pub(crate) email_check_count: EmailStatuses,
     pub(crate) is_website_online: bool,
     pub(crate) url_validated_payments: String,
@@ -50,16 +62,615 @@ pub struct PageResults {
     pub(crate) url_website: String,
     pub(crate) url_celery: String,
     pub(crate) is_celery_online: bool,
+    /// Sources restricted to a time window, keyed like the URLs
+    #[serde(skip)]
+    pub(crate) windows: BTreeMap<String, WindowedRows>,
+    /// Rows in a bad state, keyed by check name
+    #[serde(skip)]
+    pub(crate) failing_items: BTreeMap<String, Vec<FailingItem>>,
+    /// One per stuck rule whose source was fetched, in the config order
+    #[serde(skip)]
+    pub(crate) stuck: Vec<StuckRows>,
+    /// One per reconcile rule whose sources were both fetched, in the config order
+    #[serde(skip)]
+    pub(crate) reconciliations: Vec<Reconciliation>,
+    /// Sources whose page doesn't have the expected structure, keyed like the URLs
+    #[serde(skip)]
+    pub(crate) broken_sources: BTreeMap<String, BrokenSource>,
+    /// Rows parsed from each row source, keyed like the URLs
+    #[serde(skip)]
+    pub(crate) source_rows: BTreeMap<String, usize>,
+    /// Label counts of the state columns, by source then column
+    pub(crate) labels: BTreeMap<String, BTreeMap<String, LabelCounts>>,
 }
 
-fn count_vouchers_statuses(html: &str) -> VoucherStatuses {
-    let document = Html::parse_document(html);
-    let selector = Selector::parse("td.field-state").unwrap();
+/// Rows per raw label of a column
+pub type LabelCounts = BTreeMap<String, usize>;
+
+/// A source the scraper can't trust, its counts would be wrong
+pub struct BrokenSource {
+    pub(crate) url: String,
+    pub(crate) problems: Vec<String>,
+}
+
+/// Rows of a source over its window, see `config::Window`
+pub struct WindowedRows {
+    pub(crate) window: Window,
+    pub(crate) rows: usize,
+}
+
+/// A changelist row
+struct Row {
+    /// Cells keyed by admin column, e.g. `state` for `td.field-state`
+    cells: HashMap<String, String>,
+    /// Text of the row link, usually the primary key
+    id: Option<String>,
+    /// Admin change page of the row
+    change_url: Option<String>,
+}
+
+impl Row {
+    fn get(&self, column: &str) -> Option<&String> {
+        self.cells.get(column)
+    }
+}
+
+/// Result of a stuck rule over the fetched rows
+pub struct StuckRows {
+    pub(crate) rule: StuckRule,
+    /// Page of the rule's source
+    pub(crate) url: String,
+    /// Age of the oldest stuck row
+    pub(crate) oldest: Option<chrono::Duration>,
+    /// Oldest first
+    pub(crate) items: Vec<FailingItem>,
+}
+
+/// Result of a reconcile rule over the fetched rows
+pub struct Reconciliation {
+    pub(crate) rule: ReconcileRule,
+    /// Page of the `from` source
+    pub(crate) url: String,
+    /// Rows of `from` looked up in `to`
+    pub(crate) checked: usize,
+    /// Rows of `from` without counterpart in `to`
+    pub(crate) orphans: Vec<FailingItem>,
+}
+
+/// A row in a bad state, listed under its check in alerts
+#[derive(Clone, Serialize)]
+pub struct FailingItem {
+    pub(crate) id: Option<String>,
+    pub(crate) url: Option<String>,
+    pub(crate) state: String,
+    /// Identifying cells, emails masked
+    pub(crate) details: Vec<String>,
+}
+
+const PAYMENT_COLUMNS: [&str; 3] = ["state", "product_code_link", "payment_splitting"];
+const VOUCHER_COLUMNS: [&str; 1] = ["state"];
+const PAID_VOUCHER_COLUMNS: [&str; 3] = ["has_pdf", "_has_been_sent", "imported_from"];
+/// Identifying columns of HTML and JSON sources, SQL queries list theirs in `details`
+const DEFAULT_DETAILS: [&str; 4] = ["product_code_link", "code", "email", "created"];
+
+/// Admin columns read from a source by `extract_metrics`
+pub fn columns(key: &str) -> &'static [&'static str] {
+    match key {
+        "payments" => &PAYMENT_COLUMNS,
+        "vouchers" => &VOUCHER_COLUMNS,
+        "paid_vouchers" => &PAID_VOUCHER_COLUMNS,
+        _ => &[],
+    }
+}
+
+/// Labels known to the counters of each state column, the others are tracked by `labels` and
+/// alerted about once in `STRICT_COLUMNS`
+const KNOWN_VALUES: [(&str, &str, &[&str]); 5] = [
+    (
+        "payments",
+        "state",
+        &[
+            "Validated",
+            "To validate",
+            "3d secure",
+            "Cancelled",
+            "Error",
+        ],
+    ),
+    ("payments", "payment_splitting", &["Individual", "Group"]),
+    ("vouchers", "state", &["Paid", "Error"]),
+    ("paid_vouchers", "has_pdf", &["Yes", "No"]),
+    ("paid_vouchers", "_has_been_sent", &["Yes", "No", "Bulk"]),
+];
+
+/// State columns whose counters drop the labels they don't know, unlike the vouchers' `other`.
+/// New labels in them raise a scraper alert once, see `validators::validate_scraper`
+const STRICT_COLUMNS: [(&str, &str); 4] = [
+    ("payments", "state"),
+    ("payments", "payment_splitting"),
+    ("paid_vouchers", "has_pdf"),
+    ("paid_vouchers", "_has_been_sent"),
+];
+
+/// Columns to fetch from a source: the required ones and the details
+pub fn source_columns(key: &str, source: &Source, config: &Config) -> Vec<String> {
+    let mut columns = required_columns(key, config);
+    columns.extend(detail_columns(source));
+    columns.sort();
+    columns.dedup();
+    columns
+}
+
+/// Columns the metrics and rules of a source are computed from
+fn required_columns(key: &str, config: &Config) -> Vec<String> {
+    let mut columns: Vec<String> = columns(key).iter().map(|c| c.to_string()).collect();
+    for rule in config.stuck.iter().filter(|rule| rule.source == key) {
+        columns.push(rule.column.clone());
+        columns.push(rule.since.clone());
+    }
+    for rule in &config.reconcile {
+        for rows in [&rule.from, &rule.to]
+            .into_iter()
+            .filter(|rows| rows.source == key)
+        {
+            columns.extend(rows.columns());
+        }
+    }
+    columns.sort();
+    columns.dedup();
+    columns
+}
+
+pub fn detail_columns(source: &Source) -> Vec<String> {
+    let mut details = match (&source.details, source.format) {
+        (Some(details), _) => details.clone(),
+        (None, SourceFormat::Sql) => Vec::new(),
+        (None, _) => DEFAULT_DETAILS.iter().map(|d| d.to_string()).collect(),
+    };
+    if source.change_url.is_some() && !details.iter().any(|d| d == "id") {
+        details.push("id".to_string());
+    }
+    details
+}
+
+/// URLs of the changelist pages following `page`, read off its `.paginator` links. Depending on
+/// the Django version the `p` parameter counts from 0 or 1, so it is taken from the links
+pub fn next_page_urls(page: &Page) -> Vec<String> {
+    let document = Html::parse_document(&page.body);
+    let link_selector = Selector::parse(".paginator a[href]").unwrap();
+    let Ok(page_url) = reqwest::Url::parse(&page.url) else {
+        return Vec::new();
+    };
+    // Page number shown -> `p` parameter, the "Show all" link isn't numbered
+    let links: Vec<(i64, i64, reqwest::Url)> = document
+        .select(&link_selector)
+        .filter_map(|link| {
+            let number: i64 = link.text().collect::<String>().trim().parse().ok()?;
+            let url = page_url.join(link.value().attr("href")?).ok()?;
+            let p = url
+                .query_pairs()
+                .find(|(name, _)| name == "p")?
+                .1
+                .parse()
+                .ok()?;
+            Some((number, p, url))
+        })
+        .collect();
+    let Some((number, p, url)) = links.first() else {
+        return Vec::new();
+    };
+    let offset = p - number;
+    let last = links
+        .iter()
+        .map(|(number, _, _)| *number)
+        .max()
+        .unwrap_or(1);
+
+    (2..=last)
+        .map(|number| {
+            let pairs: Vec<(String, String)> = url
+                .query_pairs()
+                .filter(|(name, _)| name != "p")
+                .map(|(name, value)| (name.into_owned(), value.into_owned()))
+                .collect();
+            let mut url = url.clone();
+            url.query_pairs_mut()
+                .clear()
+                .extend_pairs(pairs)
+                .append_pair("p", &(number + offset).to_string());
+            url.to_string()
+        })
+        .collect()
+}
+
+fn html_rows(page: &Page, columns: &[String]) -> Vec<Row> {
+    std::iter::once(&page.body)
+        .chain(&page.next_bodies)
+        .flat_map(|body| body_rows(body, &page.url, columns))
+        .collect()
+}
+
+fn body_rows(body: &str, url: &str, columns: &[String]) -> Vec<Row> {
+    let document = Html::parse_document(body);
+    let row_selector = Selector::parse("table#result_list tbody tr").unwrap();
+    let link_selector = Selector::parse("th a[href]").unwrap();
+    let cell_selectors: Vec<(&String, Selector)> = columns
+        .iter()
+        .filter_map(|column| {
+            let selector = Selector::parse(&format!("td.field-{}", column)).ok()?;
+            Some((column, selector))
+        })
+        .collect();
+    let page_url = reqwest::Url::parse(url).ok();
+
+    document
+        .select(&row_selector)
+        .map(|row| {
+            let cells = cell_selectors
+                .iter()
+                .filter_map(|(column, selector)| {
+                    let cell = row.select(selector).next()?;
+                    Some((column.to_string(), cell.inner_html().trim().to_string()))
+                })
+                .collect();
+            let link = row.select(&link_selector).next();
+            let change_url = link.and_then(|link| {
+                let href = link.value().attr("href")?;
+                Some(page_url.as_ref()?.join(href).ok()?.to_string())
+            });
+
+            Row {
+                cells,
+                id: link.map(|link| link.text().collect::<String>().trim().to_string()),
+                change_url,
+            }
+        })
+        .collect()
+}
+
+/// Renders JSON values like the admin does: booleans as Yes/No, null as `-`
+fn json_text(value: &JsonValue) -> String {
+    match value {
+        JsonValue::Bool(true) => "Yes".to_string(),
+        JsonValue::Bool(false) => "No".to_string(),
+        JsonValue::Null => "-".to_string(),
+        JsonValue::String(text) => text.trim().to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn json_rows(body: &str, source: &Source, columns: &[String]) -> Result<Vec<Row>, String> {
+    let document: JsonValue =
+        serde_json::from_str(body).map_err(|e| format!("invalid JSON document: {}", e))?;
+    let rows = document
+        .pointer(&source.rows)
+        .and_then(|rows| rows.as_array())
+        .ok_or_else(|| format!("no array of rows at `{}`", source.rows))?;
+
+    let rows = rows
+        .iter()
+        .map(|row| {
+            let cells: HashMap<String, String> = columns
+                .iter()
+                .filter_map(|column| match source.fields.get(column) {
+                    Some(field) => {
+                        let value = row.pointer(field.path())?;
+                        Some((column.to_string(), field.label(json_text(value))))
+                    }
+                    None => Some((column.to_string(), json_text(row.get(column)?))),
+                })
+                .collect();
+            let id = cells.get("id").cloned();
+            let change_url = source
+                .change_url
+                .as_ref()
+                .zip(id.as_ref())
+                .map(|(template, id)| template.replace("{id}", id));
+
+            Row {
+                cells,
+                id,
+                change_url,
+            }
+        })
+        .collect();
+    Ok(rows)
+}
+
+fn rows(page: &Page, source: &Source, columns: &[String]) -> Result<Vec<Row>, String> {
+    match source.format {
+        SourceFormat::Html => Ok(html_rows(page, columns)),
+        // SQL sources are fetched as a JSON array of rows, see `sql::query_rows`
+        SourceFormat::Json | SourceFormat::Sql => json_rows(&page.body, source, columns),
+    }
+}
+
+/// Checks the rows against what the counters expect, so that a renamed class doesn't silently
+/// show as low counts
+fn structure_problems(
+    page: &Page,
+    source: &Source,
+    rows: &[Row],
+    required: &[String],
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut missing_columns = Vec::new();
+
+    if let SourceFormat::Html = source.format {
+        let document = Html::parse_document(&page.body);
+        let table_selector = Selector::parse("table#result_list").unwrap();
+        // The admin drops the table of an empty changelist
+        if document.select(&table_selector).next().is_some() {
+            for column in required {
+                let header = format!("table#result_list thead th.column-{}", column);
+                let has_header = Selector::parse(&header)
+                    .is_ok_and(|selector| document.select(&selector).next().is_some());
+                if !has_header {
+                    problems.push(format!("no `{}` column", column));
+                    missing_columns.push(column);
+                }
+            }
+        }
+    }
+
+    // Only windowed sources can legitimately be empty, see `config::Window`
+    if rows.is_empty() && source.window.is_none() {
+        problems.push("no rows".to_string());
+    }
+
+    for column in required.iter().filter(|c| !missing_columns.contains(c)) {
+        let missing = rows.iter().filter(|row| row.get(column).is_none()).count();
+        if missing > 0 {
+            problems.push(format!(
+                "`{}` missing in {}/{} rows",
+                column,
+                missing,
+                rows.len()
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Stuck rules would skip the rows whose date they can't read, e.g. after a locale change
+fn date_problems(key: &str, rows: &[Row], config: &Config) -> Vec<String> {
+    let mut columns: Vec<&str> = config
+        .stuck
+        .iter()
+        .filter(|rule| rule.source == key)
+        .map(|rule| rule.since.as_str())
+        .collect();
+    columns.sort();
+    columns.dedup();
+
+    columns
+        .into_iter()
+        .filter_map(|column| {
+            let unreadable: Vec<&str> = cells(rows, column)
+                .filter(|date| !date.is_empty() && *date != "-" && parse_date(date).is_none())
+                .collect();
+            let example = unreadable.first()?;
+            Some(format!(
+                "unreadable `{}` dates in {}/{} rows, e.g. `{}`",
+                column,
+                unreadable.len(),
+                rows.len(),
+                example
+            ))
+        })
+        .collect()
+}
+
+pub fn is_strict_column(source: &str, column: &str) -> bool {
+    STRICT_COLUMNS.contains(&(source, column))
+}
+
+pub fn is_known_label(source: &str, column: &str, label: &str) -> bool {
+    KNOWN_VALUES
+        .iter()
+        .any(|(s, c, known)| *s == source && *c == column && known.contains(&label))
+}
+
+/// Rows per raw label of each state column of the source, known or not
+fn count_labels(key: &str, rows: &[Row]) -> BTreeMap<String, LabelCounts> {
+    KNOWN_VALUES
+        .iter()
+        .filter(|(source, ..)| *source == key)
+        .map(|(_, column, _)| {
+            let mut counts = LabelCounts::new();
+            for label in cells(rows, column) {
+                *counts.entry(label.to_string()).or_default() += 1;
+            }
+            (column.to_string(), counts)
+        })
+        .collect()
+}
+
+/// RFC 3339, ISO without offset, or the admin's English display (`Oct. 18, 2026, 6:03 p.m.`).
+/// Dates without offset are in Paris time, like the admin shows them
+fn parse_date(text: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Some(date.with_timezone(&Utc));
+    }
+
+    let iso = [
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok());
+    let naive = iso.or_else(|| {
+        let mut admin = text
+            .replace("a.m.", "AM")
+            .replace("p.m.", "PM")
+            .replace("midnight", "12:00 AM")
+            .replace("noon", "12:00 PM")
+            .replace("Sept.", "Sep")
+            .replace('.', "");
+        // `6 PM` is how the admin shows 6:00 PM
+        if let Some((date, time)) = admin.rsplit_once(", ") {
+            if !time.contains(':') {
+                let (hour, period) = time.split_once(' ')?;
+                admin = format!("{}, {}:00 {}", date, hour, period);
+            }
+        }
+        // Django writes March to July in full and abbreviates the other months
+        ["%b %d, %Y, %I:%M %p", "%B %d, %Y, %I:%M %p"]
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(&admin, format).ok())
+    })?;
+
+    Paris
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|date| date.with_timezone(&Utc))
+}
 
+/// Rows of `rule.source` left in one of `rule.states` for longer than `rule.sla`
+fn stuck_rows(rows: &[Row], rule: &StuckRule, details: &[String], now: DateTime<Utc>) -> StuckRows {
+    let sla = chrono::Duration::from_std(rule.sla).unwrap_or(chrono::Duration::MAX);
+    let mut stuck: Vec<(chrono::Duration, FailingItem)> = rows
+        .iter()
+        .filter_map(|row| {
+            let state = row.get(&rule.column)?;
+            if !rule.states.contains(state) {
+                return None;
+            }
+            let age = now - parse_date(row.get(&rule.since)?)?;
+            if age <= sla {
+                return None;
+            }
+            let mut item =
+                failing_items(std::slice::from_ref(row), &rule.column, &[state], details).pop()?;
+            item.state = format!("{} for {}", state, format_age(age));
+            Some((age, item))
+        })
+        .collect();
+    // Oldest first, they are listed first in the drill-down
+    stuck.sort_by_key(|(age, _)| std::cmp::Reverse(*age));
+
+    StuckRows {
+        rule: rule.clone(),
+        url: String::new(),
+        oldest: stuck.first().map(|(age, _)| *age),
+        items: stuck.into_iter().map(|(_, item)| item).collect(),
+    }
+}
+
+/// Minutes precision, e.g. `2h 5m`
+pub fn format_age(age: chrono::Duration) -> String {
+    let minutes = age.num_minutes().max(0) as u64;
+    humantime::format_duration(std::time::Duration::from_secs(minutes * 60)).to_string()
+}
+
+/// Text of a cell, without the markup of link columns like `product_code_link`
+fn plain_text(cell: &str) -> String {
+    Html::parse_fragment(cell)
+        .root_element()
+        .text()
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+fn in_row_set<'a>(rows: &'a [Row], set: &'a RowSet) -> impl Iterator<Item = &'a Row> {
+    rows.iter().filter(move |row| match &set.column {
+        Some(column) => row
+            .get(column)
+            .is_some_and(|state| set.states.contains(state)),
+        None => true,
+    })
+}
+
+fn reconcile(
+    rule: &ReconcileRule,
+    from_rows: &[Row],
+    to_rows: &[Row],
+    details: &[String],
+) -> Reconciliation {
+    let to_keys: HashSet<String> = in_row_set(to_rows, &rule.to)
+        .filter_map(|row| row.get(&rule.to.key).map(|key| plain_text(key)))
+        .collect();
+    let from: Vec<(&Row, String)> = in_row_set(from_rows, &rule.from)
+        .filter_map(|row| Some((row, plain_text(row.get(&rule.from.key)?))))
+        .collect();
+
+    let orphans = from
+        .iter()
+        .filter(|(_, key)| !to_keys.contains(key))
+        .map(|(row, key)| FailingItem {
+            id: row.id.clone(),
+            url: row.change_url.clone(),
+            state: format!("{} missing from {}", key, rule.to.source),
+            details: row_details(row, details),
+        })
+        .collect();
+
+    Reconciliation {
+        rule: rule.clone(),
+        url: String::new(),
+        checked: from.len(),
+        orphans,
+    }
+}
+
+fn row_details(row: &Row, details: &[String]) -> Vec<String> {
+    details
+        .iter()
+        .filter(|detail| *detail != "id")
+        .filter_map(|detail| row.get(detail))
+        .map(|value| plain_text(value))
+        .filter(|value| !value.is_empty() && value != "-")
+        .map(|value| mask_email(&value))
+        .collect()
+}
+
+/// `jane.doe@example.com` -> `j***@example.com`
+fn mask_email(value: &str) -> String {
+    match value.split_once('@') {
+        Some((user, domain)) => {
+            let first = user.chars().next().map(String::from).unwrap_or_default();
+            format!("{}***@{}", first, domain)
+        }
+        None => value.to_string(),
+    }
+}
+
+/// Rows whose `column` is one of `bad_states`
+fn failing_items(
+    rows: &[Row],
+    column: &str,
+    bad_states: &[&str],
+    details: &[String],
+) -> Vec<FailingItem> {
+    rows.iter()
+        .filter_map(|row| {
+            let state = row.get(column)?;
+            if !bad_states.contains(&state.as_str()) {
+                return None;
+            }
+            Some(FailingItem {
+                id: row.id.clone(),
+                url: row.change_url.clone(),
+                state: state.clone(),
+                details: row_details(row, details),
+            })
+        })
+        .collect()
+}
+
+fn cells<'a>(rows: &'a [Row], column: &'a str) -> impl Iterator<Item = &'a str> {
+    rows.iter()
+        .filter_map(move |row| row.get(column).map(|cell| cell.as_str()))
+}
+
+fn count_vouchers_statuses(rows: &[Row]) -> VoucherStatuses {
     let mut voucher_status = VoucherStatuses::default();
 
-    for element in document.select(&selector) {
-        match element.inner_html().trim() {
+    for state in cells(rows, "state") {
+        match state {
             "Paid" => voucher_status.paid += 1,
             "Error" => voucher_status.error += 1,
             _ => voucher_status.other += 1,
@@ -69,24 +680,15 @@ fn count_vouchers_statuses(html: &str) -> VoucherStatuses {
     voucher_status
 }
 
-fn count_pdf(html: &str) -> usize {
-    let document = Html::parse_document(html);
-    let selector = Selector::parse("td.field-has_pdf").unwrap();
-
-    document
-        .select(&selector)
-        .filter(|element| element.inner_html().trim() == "Yes")
-        .count()
+fn count_pdf(rows: &[Row]) -> usize {
+    cells(rows, "has_pdf").filter(|cell| *cell == "Yes").count()
 }
 
-fn count_email_statuses(html: &str) -> EmailStatuses {
-    let document = Html::parse_document(html);
-    let selector = Selector::parse("td.field-_has_been_sent").unwrap();
-
+fn count_email_statuses(rows: &[Row]) -> EmailStatuses {
     let mut email_status = EmailStatuses::default();
 
-    for element in document.select(&selector) {
-        match element.inner_html().trim() {
+    for status in cells(rows, "_has_been_sent") {
+        match status {
             "Yes" => email_status.sent += 1,
             "No" => email_status.not_sent += 1,
             "Bulk" => email_status.bulk += 1,
@@ -97,58 +699,58 @@ fn count_email_statuses(html: &str) -> EmailStatuses {
     email_status
 }
 
-fn count_not_imported(html: &str) -> usize {
-    let document = Html::parse_document(html);
-    let selector = Selector::parse("td.field-imported_from").unwrap();
-
-    document
-        .select(&selector)
-        .filter(|element| element.inner_html().trim() == "-")
+/// Not imported paid vouchers whose `column` label is unknown
+fn count_unknown(rows: &[Row], column: &str) -> usize {
+    rows.iter()
+        .filter(|row| row.get("imported_from").is_some_and(|cell| cell == "-"))
+        .filter(|row| {
+            row.get(column)
+                .is_some_and(|label| !is_known_label("paid_vouchers", column, label))
+        })
         .count()
 }
 
-fn count_payment_statuses(html: &str) -> PaymentStatuses {
-    let document = Html::parse_document(html);
-    let row_selector = Selector::parse("table#result_list tbody tr").unwrap();
-    let state_selector = Selector::parse("td.field-state").unwrap();
-    let code_selector = Selector::parse("td.field-product_code_link").unwrap();
+fn count_not_imported(rows: &[Row]) -> usize {
+    cells(rows, "imported_from")
+        .filter(|cell| *cell == "-")
+        .count()
+}
 
+fn count_payment_statuses(rows: &[Row]) -> PaymentStatuses {
     let mut payment_statuses = PaymentStatuses::default();
     let mut processed_codes = HashSet::new();
 
-    for row in document.select(&row_selector) {
-        let state_element = row.select(&state_selector).next().unwrap();
-        let code_element = row.select(&code_selector).next().unwrap();
+    for row in rows {
+        let (Some(state), Some(product_code)) = (row.get("state"), row.get("product_code_link"))
+        else {
+            continue;
+        };
 
-        let product_code = code_element.inner_html().trim().to_string();
-        if processed_codes.contains(&product_code) {
+        if processed_codes.contains(product_code) {
             payment_statuses.group += 1;
             continue;
         }
 
         processed_codes.insert(product_code.to_string());
 
-        match state_element.inner_html().trim() {
+        match state.as_str() {
             "Validated" => payment_statuses.validated += 1,
             "To validate" => payment_statuses.to_validate += 1,
             "3d secure" => payment_statuses.threed_secure += 1,
             "Cancelled" => payment_statuses.cancelled += 1,
             "Error" => payment_statuses.error += 1,
-            _ => {}
+            _ => payment_statuses.unknown += 1,
         }
     }
 
     payment_statuses
 }
 
-fn count_payment_types(html: &str) -> PaymentTypes {
-    let document = Html::parse_document(html);
-    let selector = Selector::parse("td.field-payment_splitting").unwrap();
-
+fn count_payment_types(rows: &[Row]) -> PaymentTypes {
     let mut payment_types = PaymentTypes::default();
 
-    for element in document.select(&selector) {
-        match element.inner_html().trim() {
+    for payment_type in cells(rows, "payment_splitting") {
+        match payment_type {
             "Individual" => payment_types.individual += 1,
             "Group" => payment_types.group += 1,
             _ => {}
@@ -178,7 +780,12 @@ fn get_celery_status(html: &str) -> bool {
     true
 }
 
-pub fn extract_metrics(html_contents: &HashMap<String, Page>, is_test_mode: bool) -> PageResults {
+/// Sources are HTML changelists unless configured as JSON, see `config::Source`
+pub fn extract_metrics(
+    html_contents: &HashMap<String, Page>,
+    config: &Config,
+    is_test_mode: bool,
+) -> PageResults {
     let mut results = PageResults::default();
 
     if is_test_mode {
@@ -190,6 +797,7 @@ pub fn extract_metrics(html_contents: &HashMap<String, Page>, is_test_mode: bool
                 cancelled: 0,
                 error: 0,
                 group: 0,
+                unknown: 0,
             },
             payment_types_count: PaymentTypes {
                 individual: 80,
@@ -201,7 +809,9 @@ pub fn extract_metrics(html_contents: &HashMap<String, Page>, is_test_mode: bool
                 other: 50,
             },
             not_imported_count: 50,
+            unknown_pdf_count: 0,
             pdf_count: 76,
+            unknown_email_count: 0,
             email_check_count: EmailStatuses {
                 sent: 30,
                 not_sent: 50,
@@ -215,38 +825,217 @@ pub fn extract_metrics(html_contents: &HashMap<String, Page>, is_test_mode: bool
             url_website: "https://test-domain.com".to_string(),
             url_celery: "https://test-domain.com".to_string(),
             is_celery_online: true,
+            windows: BTreeMap::new(),
+            failing_items: BTreeMap::new(),
+            stuck: Vec::new(),
+            reconciliations: Vec::new(),
+            broken_sources: BTreeMap::new(),
+            source_rows: BTreeMap::new(),
+            labels: BTreeMap::new(),
         }
     }
 
+    let now = Utc::now();
+    let mut windows = BTreeMap::new();
+    let mut stuck = Vec::new();
+    let mut broken_sources = BTreeMap::new();
+    let mut labels = BTreeMap::new();
+    let mut row_counts = BTreeMap::new();
+    let mut source_rows = |key: &str, page: &Page| {
+        let source = config.source(key);
+        let details = detail_columns(&source);
+        let (rows, problems) = match rows(page, &source, &source_columns(key, &source, config)) {
+            Ok(rows) => {
+                let required = required_columns(key, config);
+                let mut problems = structure_problems(page, &source, &rows, &required);
+                problems.extend(date_problems(key, &rows, config));
+                labels.insert(key.to_string(), count_labels(key, &rows));
+                (rows, problems)
+            }
+            Err(e) => (Vec::new(), vec![e]),
+        };
+        row_counts.insert(key.to_string(), rows.len());
+        if !problems.is_empty() {
+            error!(source = key; "Scraper broken: {}", problems.join(", "));
+            let broken = BrokenSource {
+                url: page.url.clone(),
+                problems,
+            };
+            broken_sources.insert(key.to_string(), broken);
+        }
+        for rule in config.stuck.iter().filter(|rule| rule.source == key) {
+            let mut stuck_rows = stuck_rows(&rows, rule, &details, now);
+            stuck_rows.url = page.url.clone();
+            stuck.push(stuck_rows);
+        }
+        if let Some(window) = source.window {
+            let windowed = WindowedRows {
+                window,
+                rows: rows.len(),
+            };
+            windows.insert(key.to_string(), windowed);
+        }
+        (rows, details)
+    };
+    let mut failing = BTreeMap::new();
+    // Kept for the reconciliations, which join rows across sources
+    let mut parsed: HashMap<&str, (Vec<Row>, Vec<String>, String)> = HashMap::new();
+
     if let Some(page) = html_contents.get("payments") {
-        results.validated_payments_count = count_payment_statuses(&page.html);
-        results.payment_types_count = count_payment_types(&page.html);
+        let (rows, details) = source_rows("payments", page);
+        results.validated_payments_count = count_payment_statuses(&rows);
+        results.payment_types_count = count_payment_types(&rows);
+        let items = failing_items(&rows, "state", &["Error", "3d secure"], &details);
+        failing.insert("Validated payments".to_string(), items);
         results.url_validated_payments = page.url.clone();
+        parsed.insert("payments", (rows, details, page.url.clone()));
     }
 
     if let Some(page) = html_contents.get("paid_vouchers") {
-        results.pdf_count = count_pdf(&page.html);
-        results.email_check_count = count_email_statuses(&page.html);
-        results.not_imported_count = count_not_imported(&page.html);
+        let (rows, details) = source_rows("paid_vouchers", page);
+        results.pdf_count = count_pdf(&rows);
+        results.email_check_count = count_email_statuses(&rows);
+        results.not_imported_count = count_not_imported(&rows);
+        results.unknown_pdf_count = count_unknown(&rows, "has_pdf");
+        results.unknown_email_count = count_unknown(&rows, "_has_been_sent");
+        let items = failing_items(&rows, "has_pdf", &["No"], &details);
+        failing.insert("PDF count".to_string(), items);
+        let items = failing_items(&rows, "_has_been_sent", &["No"], &details);
+        failing.insert("Email count".to_string(), items);
         let url = page.url.clone();
         results.url_pdf_count = url.clone();
-        results.url_email_check_count = url;
+        results.url_email_check_count = url.clone();
+        parsed.insert("paid_vouchers", (rows, details, url));
     }
 
     if let Some(page) = html_contents.get("vouchers") {
-        results.paid_vouchers_count = count_vouchers_statuses(&page.html);
+        let (rows, details) = source_rows("vouchers", page);
+        results.paid_vouchers_count = count_vouchers_statuses(&rows);
+        let items = failing_items(&rows, "state", &["Error"], &details);
+        failing.insert("Paid vouchers".to_string(), items);
         results.url_vouchers_count = page.url.clone();
+        parsed.insert("vouchers", (rows, details, page.url.clone()));
     }
 
     if let Some(page) = html_contents.get("purchase_website") {
         results.url_website = page.url.clone();
-        results.is_website_online = has_correct_content(&page.html);
+        results.is_website_online = has_correct_content(&page.body);
     }
 
     if let Some(page) = html_contents.get("celery") {
         results.url_celery = page.url.clone();
-        results.is_celery_online = get_celery_status(&page.html);
+        results.is_celery_online = get_celery_status(&page.body);
     }
 
+    results.windows = windows;
+    results.failing_items = failing;
+    results.broken_sources = broken_sources;
+    results.labels = labels;
+    results.source_rows = row_counts;
+    // Back in the config order, sources are fetched in a fixed but different one
+    stuck.sort_by_key(|rows: &StuckRows| {
+        config
+            .stuck
+            .iter()
+            .position(|rule| rule.name == rows.rule.name)
+    });
+    results.stuck = stuck;
+    results.reconciliations = config
+        .reconcile
+        .iter()
+        .filter_map(|rule| {
+            let (from_rows, details, url) = parsed.get(rule.from.source.as_str())?;
+            let (to_rows, _, _) = parsed.get(rule.to.source.as_str())?;
+            let mut reconciliation = reconcile(rule, from_rows, to_rows, details);
+            reconciliation.url = url.clone();
+            Some(reconciliation)
+        })
+        .collect();
     results
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Datelike, Timelike};
+
+    use super::*;
+
+    fn changelist(body: &str) -> Page {
+        Page {
+            url: "https://admin.test/admin/payment/?created__gte=2026-10-18T08%3A00%3A00Z"
+                .to_string(),
+            body: body.to_string(),
+            next_bodies: Vec::new(),
+            status: Some(200),
+            elapsed_ms: 0,
+        }
+    }
+
+    #[test]
+    fn lists_the_following_changelist_pages() {
+        let page = changelist(
+            r#"<p class="paginator"><span class="this-page">1</span>
+            <a href="?created__gte=2026-10-18T08%3A00%3A00Z&amp;p=2">2</a>
+            <a href="?created__gte=2026-10-18T08%3A00%3A00Z&amp;p=3" class="end">3</a>
+            250 payments <a href="?all=&amp;created__gte=2026-10-18T08%3A00%3A00Z">Show all</a></p>"#,
+        );
+        assert_eq!(
+            next_page_urls(&page),
+            [
+                "https://admin.test/admin/payment/?created__gte=2026-10-18T08%3A00%3A00Z&p=2",
+                "https://admin.test/admin/payment/?created__gte=2026-10-18T08%3A00%3A00Z&p=3",
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_the_pages_hidden_by_the_paginator_ellipsis() {
+        // Before Django 4.0, `p` counts from 0
+        let page = changelist(
+            r#"<p class="paginator"><span class="this-page">1</span>
+            <a href="?p=1">2</a> <a href="?p=2">3</a> <a href="?p=3">4</a> …
+            <a href="?p=5">6</a> <a href="?p=6" class="end">7</a> 700 payments</p>"#,
+        );
+        let urls = next_page_urls(&page);
+        assert_eq!(urls.len(), 6);
+        assert_eq!(urls[3], "https://admin.test/admin/payment/?p=4");
+    }
+
+    #[test]
+    fn has_no_following_page_without_paginator_links() {
+        let page = changelist(r#"<p class="paginator">5 payments</p>"#);
+        assert!(next_page_urls(&page).is_empty());
+    }
+
+    #[test]
+    fn parses_every_admin_month() {
+        let months = [
+            "Jan.", "Feb.", "March", "April", "May", "June", "July", "Aug.", "Sept.", "Oct.",
+            "Nov.", "Dec.",
+        ];
+        for (index, month) in months.iter().enumerate() {
+            let text = format!("{} 3, 2026, 6:03 p.m.", month);
+            let date = parse_date(&text).unwrap_or_else(|| panic!("`{}` wasn't parsed", text));
+            let local = date.with_timezone(&Paris);
+            assert_eq!(local.month0() as usize, index, "{}", text);
+            assert_eq!(
+                (local.day(), local.hour(), local.minute()),
+                (3, 18, 3),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn parses_admin_noon_midnight_and_whole_hours() {
+        for (text, hour) in [
+            ("March 3, 2026, noon", 12),
+            ("March 3, 2026, midnight", 0),
+            ("March 3, 2026, 6 a.m.", 6),
+        ] {
+            let date = parse_date(text).unwrap_or_else(|| panic!("`{}` wasn't parsed", text));
+            assert_eq!(date.with_timezone(&Paris).hour(), hour, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use crate::output::exit_code;
+use crate::validators::{Status, UnitValidationResult, Value};
+
+/// Exit code of a plugin that couldn't determine the state of the check
+pub const UNKNOWN: i32 = 3;
+
+fn label(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Accepts the check name in any case, or its perfdata label (`validated_payments`)
+pub fn matches(name: &str, query: &str) -> bool {
+    name.eq_ignore_ascii_case(query) || label(name) == label(query)
+}
+
+/// `label=value;warn;crit;min;max`, thresholds use the `N:` range syntax as lower values are worse
+fn perfdata(result: &UnitValidationResult) -> String {
+    let label = label(&result.name);
+    match (&result.value, &result.limits) {
+        (Value::Count(count), Some(limits)) => format!(
+            "{}={};{}:;{}:;0;{}",
+            label, count, limits.warning, limits.critical, limits.max
+        ),
+        (Value::Count(count), None) => format!("{}={};;;0;", label, count),
+        (Value::Bool(is_ok), _) => format!("{}={};;1:;0;1", label, *is_ok as u8),
+    }
+}
+
+/// One-line plugin output and exit code
+pub fn check_output(result: &UnitValidationResult) -> (String, i32) {
+    let state = match result.status {
+        Status::Ok => "OK",
+        Status::Warning => "WARNING",
+        Status::Alert => "CRITICAL",
+    };
+    let line = format!(
+        "BEEBOT {} - {}: {} | {}",
+        state,
+        result.name,
+        result.message.replace('`', ""),
+        perfdata(result)
+    );
+    (line, exit_code(&result.status))
+}
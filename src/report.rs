@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use clap::ValueEnum;
+
+use crate::db::{CheckResult, LogEntry};
+use crate::validators::{Status, CHECK_NAMES};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Period {
+    Daily,
+    Weekly,
+}
+
+impl Period {
+    pub fn duration(&self) -> Duration {
+        match self {
+            Period::Daily => Duration::days(1),
+            Period::Weekly => Duration::weeks(1),
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Period::Daily => "daily",
+            Period::Weekly => "weekly",
+        }
+    }
+}
+
+struct CheckSummary {
+    name: String,
+    runs: usize,
+    warnings: usize,
+    alerts: usize,
+}
+
+struct MetricSummary {
+    name: &'static str,
+    min: i32,
+    avg: f64,
+    max: i32,
+}
+
+struct Incident {
+    name: String,
+    start: NaiveDateTime,
+    /// `None` while the check is still alerting
+    end: Option<NaiveDateTime>,
+}
+
+pub struct Report {
+    period: Period,
+    since: NaiveDateTime,
+    until: NaiveDateTime,
+    runs: usize,
+    checks: Vec<CheckSummary>,
+    metrics: Vec<MetricSummary>,
+    incidents: Vec<Incident>,
+}
+
+fn parse_datetime(value: &Option<String>) -> Option<NaiveDateTime> {
+    value
+        .as_ref()
+        .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+        .map(|date| date.naive_utc())
+}
+
+fn summarize_metric(name: &'static str, values: Vec<i32>) -> Option<MetricSummary> {
+    let min = *values.iter().min()?;
+    let max = *values.iter().max()?;
+    let avg = values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64;
+
+    Some(MetricSummary {
+        name,
+        min,
+        avg,
+        max,
+    })
+}
+
+/// `logs` must be ordered from oldest to newest
+pub fn build_report(
+    period: Period,
+    until: DateTime<Utc>,
+    logs: &[LogEntry],
+    check_results: &[CheckResult],
+) -> Report {
+    let mut results_by_run: HashMap<i32, Vec<&CheckResult>> = HashMap::new();
+    for result in check_results {
+        results_by_run
+            .entry(result.log_id)
+            .or_default()
+            .push(result);
+    }
+
+    let mut checks: Vec<CheckSummary> = CHECK_NAMES
+        .iter()
+        .map(|name| CheckSummary {
+            name: name.to_string(),
+            runs: 0,
+            warnings: 0,
+            alerts: 0,
+        })
+        .collect();
+    let mut incidents: Vec<Incident> = Vec::new();
+    let mut open_incidents: HashMap<String, NaiveDateTime> = HashMap::new();
+
+    for log in logs {
+        let (Some(run_id), Some(run_date)) = (log.id, parse_datetime(&log.started_at)) else {
+            continue;
+        };
+
+        for result in results_by_run.get(&run_id).into_iter().flatten() {
+            let summary = match checks.iter_mut().find(|c| c.name == result.name) {
+                Some(summary) => summary,
+                None => {
+                    checks.push(CheckSummary {
+                        name: result.name.clone(),
+                        runs: 0,
+                        warnings: 0,
+                        alerts: 0,
+                    });
+                    checks.last_mut().unwrap()
+                }
+            };
+            summary.runs += 1;
+
+            let is_alert = result.status == Status::Alert.as_str();
+            if is_alert {
+                summary.alerts += 1;
+                open_incidents
+                    .entry(result.name.clone())
+                    .or_insert(run_date);
+            } else {
+                if result.status == Status::Warning.as_str() {
+                    summary.warnings += 1;
+                }
+                if let Some(start) = open_incidents.remove(&result.name) {
+                    incidents.push(Incident {
+                        name: result.name.clone(),
+                        start,
+                        end: Some(run_date),
+                    });
+                }
+            }
+        }
+    }
+
+    for (name, start) in open_incidents {
+        incidents.push(Incident {
+            name,
+            start,
+            end: None,
+        });
+    }
+    incidents.sort_by_key(|incident| incident.start);
+    checks.retain(|check| check.runs > 0);
+
+    let metrics = [
+        (
+            "Validated payments",
+            logs.iter().map(|l| l.payments).collect(),
+        ),
+        ("Paid vouchers", logs.iter().map(|l| l.vouchers).collect()),
+        ("PDF count", logs.iter().map(|l| l.pdf_count).collect()),
+        ("Email count", logs.iter().map(|l| l.email_count).collect()),
+    ]
+    .into_iter()
+    .filter_map(|(name, values)| summarize_metric(name, values))
+    .collect();
+
+    Report {
+        period,
+        since: (until - period.duration()).naive_utc(),
+        until: until.naive_utc(),
+        runs: logs.len(),
+        checks,
+        metrics,
+        incidents,
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.num_minutes().max(0);
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{}m", minutes),
+        (hours, 0) => format!("{}h", hours),
+        (hours, minutes) => format!("{}h{:02}m", hours, minutes),
+    }
+}
+
+/// Slack flavoured text, the email uses the same content without markup
+pub fn format_report(report: &Report, is_test_mode: bool) -> String {
+    let date_format = "%Y-%m-%d %H:%M";
+    let mut message = "".to_string();
+
+    if is_test_mode {
+        message.push_str("*THIS IS A TEST*\n");
+    }
+
+    message.push_str(&format!(
+        "*Beebot {} report* {} → {} UTC, `{}` runs\n",
+        report.period.label(),
+        report.since.format(date_format),
+        report.until.format(date_format),
+        report.runs
+    ));
+
+    message.push_str("\n*Checks*\n");
+    if report.checks.is_empty() {
+        message.push_str("No check result recorded\n");
+    }
+    for check in &report.checks {
+        let uptime = 100.0 * (check.runs - check.alerts) as f64 / check.runs as f64;
+        message.push_str(&format!(
+            "• {}: `{:.1}%` uptime, `{}` alert runs, `{}` warning runs\n",
+            check.name, uptime, check.alerts, check.warnings
+        ));
+    }
+
+    message.push_str("\n*Metrics*\n");
+    if report.metrics.is_empty() {
+        message.push_str("No run recorded\n");
+    }
+    for metric in &report.metrics {
+        message.push_str(&format!(
+            "• {}: min `{}` avg `{:.1}` max `{}`\n",
+            metric.name, metric.min, metric.avg, metric.max
+        ));
+    }
+
+    message.push_str("\n*Incidents*\n");
+    if report.incidents.is_empty() {
+        message.push_str("None\n");
+    }
+    for incident in &report.incidents {
+        let line = match incident.end {
+            Some(end) => format!(
+                "• {}: {} → {} ({})\n",
+                incident.name,
+                incident.start.format(date_format),
+                end.format(date_format),
+                format_duration(end - incident.start)
+            ),
+            None => format!(
+                "• {}: since {} (ongoing, {})\n",
+                incident.name,
+                incident.start.format(date_format),
+                format_duration(report.until - incident.start)
+            ),
+        };
+        message.push_str(&line);
+    }
+
+    message
+}
+
+pub fn report_subject(period: Period, is_test_mode: bool) -> String {
+    let test_subject = if is_test_mode {
+        "THIS IS A TEST - "
+    } else {
+        ""
+    };
+    format!("📊 {}Beebot {} report", test_subject, period.label())
+}
@@ -1,82 +1,247 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
 
+use chrono::{DateTime, Utc};
+use diesel::ConnectionError;
 use futures::future;
-use http_auth_basic::Credentials;
-use log::error;
-use reqwest;
-use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use log::{error, warn};
+use reqwest::StatusCode;
+
+use crate::auth::{is_login_form, is_login_redirect, AuthError, InvalidCredentials, Session};
+use crate::config::{Config, Source, SourceFormat, Window};
+use crate::db::{self, DbConnection, DjangoSession};
+use crate::{parser, sql};
+
+/// Changelist pages fetched after the first one of a windowed HTML source
+const MAX_NEXT_PAGES: usize = 49;
 
 pub struct Page {
     pub(crate) url: String,
-    pub(crate) html: String,
+    /// HTML or JSON, depending on the source format
+    pub(crate) body: String,
+    /// Following changelist pages of a windowed HTML source, see `fetch_changelist`
+    pub(crate) next_bodies: Vec<String>,
+    /// HTTP status, `None` for SQL sources
+    pub(crate) status: Option<u16>,
+    pub(crate) elapsed_ms: u128,
 }
 
-async fn fetch_html(
-    key: &str,
+pub struct Fetched {
+    pub(crate) pages: HashMap<String, Page>,
+    /// Sources that rejected Beebot, keyed like the URLs
+    pub(crate) invalid_credentials: BTreeMap<String, InvalidCredentials>,
+    /// Django sessions opened by this run, see `save_sessions`
+    pub(crate) sessions: HashMap<String, String>,
+}
+
+/// Login URL -> `Cookie` header of the Django sessions of previous runs, so that runs don't
+/// log in again until the session expires. None without database
+pub fn saved_sessions(conn: &mut Result<DbConnection, ConnectionError>) -> HashMap<String, String> {
+    let Ok(conn) = conn.as_mut() else {
+        return HashMap::new();
+    };
+    match db::get_django_sessions(conn) {
+        Ok(sessions) => sessions
+            .into_iter()
+            .map(|session| (session.login_url, session.cookies))
+            .collect(),
+        Err(e) => {
+            error!("Error fetching Django sessions: {:?}", e);
+            HashMap::new()
+        }
+    }
+}
+
+pub fn save_sessions(conn: &mut DbConnection, sessions: &HashMap<String, String>) {
+    let saved_at = db::timestamp(Utc::now());
+    for (login_url, cookies) in sessions {
+        let session = DjangoSession {
+            login_url: login_url.clone(),
+            cookies: cookies.clone(),
+            saved_at: saved_at.clone(),
+        };
+        if let Err(e) = db::save_django_session(conn, &session) {
+            error!("Failed to store the session of {}: {:?}", login_url, e);
+        }
+    }
+}
+
+fn invalid_credentials(url: &str, reason: &str) -> AuthError {
+    InvalidCredentials {
+        url: url.to_string(),
+        reason: reason.to_string(),
+    }
+    .into()
+}
+
+/// An expired session or token shows up as 401/403 or as Django's login page, which would
+/// otherwise parse as a page without any row
+async fn fetch_html(session: &Session, url: &str, source: &Source) -> Result<Page, AuthError> {
+    let started = Instant::now();
+    let res = session.get(url, source).await?;
+    let status = res.status();
+
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        return Err(invalid_credentials(
+            url,
+            &format!("HTTP {}", status.as_u16()),
+        ));
+    }
+    if !status.is_success() {
+        return Err(res.error_for_status().unwrap_err().into());
+    }
+    if is_login_redirect(res.url()) {
+        return Err(invalid_credentials(url, "redirected to the login page"));
+    }
+
+    let body = res.text().await?;
+    if is_login_form(&body) {
+        return Err(invalid_credentials(url, "login page instead of data"));
+    }
+
+    Ok(Page {
+        url: url.to_string(),
+        body,
+        next_bodies: Vec::new(),
+        status: Some(status.as_u16()),
+        elapsed_ms: started.elapsed().as_millis(),
+    })
+}
+
+/// RFC 3339 in UTC, which Django filters, Postgres and SQLite's `datetime()` all parse.
+/// Without window, SQL queries get the current time
+fn window_start(source: &Source, now: DateTime<Utc>) -> String {
+    match &source.window {
+        Some(window) => db::timestamp(now - window.duration),
+        None => db::timestamp(now),
+    }
+}
+
+/// Adds the window start to the query string, so the admin filters its changelist by date
+fn windowed_url(url: &str, window: &Window, window_start: &str) -> Result<String, AuthError> {
+    let mut url = reqwest::Url::parse(url)?;
+    url.query_pairs_mut()
+        .append_pair(&window.param, window_start);
+    Ok(url.to_string())
+}
+
+/// Rows of a window may not fit in one changelist page, the following ones are fetched too.
+/// Their number is capped, so a wrong window can't have a run crawl the whole table
+async fn fetch_changelist(
+    session: &Session,
     url: &str,
-    api_token: &str,
-    celery_username: &str,
-    celery_password: &str,
-) -> Result<(String, String), reqwest::Error> {
-    let client = reqwest::Client::new();
-    let mut headers = HeaderMap::new();
-
-    if key == "celery" {
-        headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
-        let credentials = Credentials::new(celery_username, celery_password);
-        headers.insert(
-            AUTHORIZATION,
-            HeaderValue::from_str(&credentials.as_http_header()).unwrap(),
+    source: &Source,
+) -> Result<Page, AuthError> {
+    let mut page = fetch_html(session, url, source).await?;
+    let mut next_pages = parser::next_page_urls(&page);
+    if next_pages.len() > MAX_NEXT_PAGES {
+        warn!(
+            "Only counting {} of the {} changelist pages of {}",
+            MAX_NEXT_PAGES + 1,
+            next_pages.len() + 1,
+            url
         );
-    } else {
-        let auth_value = HeaderValue::from_str(&format!("{}", api_token)).unwrap();
-        headers.insert(AUTHORIZATION, auth_value);
+        next_pages.truncate(MAX_NEXT_PAGES);
     }
 
-    let res = client.get(url).headers(headers).send().await?;
-
-    if res.status().is_success() {
-        Ok((url.to_string(), res.text().await?))
-    } else {
-        Err(res.error_for_status().unwrap_err())
+    for next_url in next_pages {
+        let next_page = fetch_html(session, &next_url, source).await?;
+        page.elapsed_ms += next_page.elapsed_ms;
+        page.next_bodies.push(next_page.body);
     }
+    Ok(page)
+}
+
+/// `url` is kept as the link shown with the results, the rows come from the database
+async fn fetch_sql(
+    session: &Session,
+    url: &str,
+    key: &str,
+    source: &Source,
+    config: &Config,
+    window_start: &str,
+) -> Result<Page, AuthError> {
+    let started = Instant::now();
+    let (Some(database_secret), Some(query)) = (&source.database_secret, &source.query) else {
+        return Err(format!("SQL source {} needs `database_secret` and `query`", key).into());
+    };
+    let db_url = session.secret(database_secret).await?;
+    let query = query.replace("{window_start}", &format!("'{}'", window_start));
+    let columns = parser::source_columns(key, source, config);
+
+    let body =
+        tokio::task::spawn_blocking(move || sql::query_rows(&db_url, &query, &columns)).await??;
+
+    Ok(Page {
+        url: url.to_string(),
+        body,
+        next_bodies: Vec::new(),
+        status: None,
+        elapsed_ms: started.elapsed().as_millis(),
+    })
 }
 
 pub async fn request_pages(
-    api_token: &str,
-    urls: &Vec<(&str, String)>,
-    celery_username: &str,
-    celery_password: &str,
+    urls: &[(&str, String)],
+    config: &Config,
+    saved_sessions: &HashMap<String, String>,
     is_test_mode: bool,
-) -> HashMap<String, Page> {
+) -> Fetched {
+    let mut fetched = Fetched {
+        pages: HashMap::new(),
+        invalid_credentials: BTreeMap::new(),
+        sessions: HashMap::new(),
+    };
     if is_test_mode {
-        return HashMap::new();
+        return fetched;
     }
 
+    let session = Session::new(saved_sessions);
+    let now = Utc::now();
     let futures = urls
         .iter()
-        .map(|(key, url)| async move {
-            (
-                key.to_string(),
-                fetch_html(key, &url, &api_token, celery_username, celery_password).await,
-            )
+        .map(|(key, url)| {
+            let session = &session;
+            let source = config.source(key);
+            async move {
+                let window_start = window_start(&source, now);
+                let page = match (source.format, &source.window) {
+                    (SourceFormat::Sql, _) => {
+                        fetch_sql(session, url, key, &source, config, &window_start).await
+                    }
+                    (SourceFormat::Html, Some(window)) => {
+                        match windowed_url(url, window, &window_start) {
+                            Ok(url) => fetch_changelist(session, &url, &source).await,
+                            Err(e) => Err(e),
+                        }
+                    }
+                    (_, Some(window)) => match windowed_url(url, window, &window_start) {
+                        Ok(url) => fetch_html(session, &url, &source).await,
+                        Err(e) => Err(e),
+                    },
+                    (_, None) => fetch_html(session, url, &source).await,
+                };
+                (key.to_string(), page)
+            }
         })
         .collect::<Vec<_>>();
 
     let results = future::join_all(futures).await;
-
-    let mut html_contents = HashMap::new();
+    fetched.sessions = session.renewed().await;
 
     for (key, result) in results {
         match result {
-            Ok((url, html)) => {
-                let page = Page { url, html };
-                html_contents.insert(key, page);
-            }
-            Err(e) => {
-                error!("Error while fetching pages: {}", e)
+            Ok(page) => {
+                fetched.pages.insert(key, page);
             }
+            Err(e) => match e.downcast::<InvalidCredentials>() {
+                Ok(e) => {
+                    warn!(source = key.as_str(); "Credentials rejected: {}", e);
+                    fetched.invalid_credentials.insert(key, *e);
+                }
+                Err(e) => error!("Error while fetching pages: {}", e),
+            },
         }
     }
-    html_contents
+    fetched
 }
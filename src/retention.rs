@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, NaiveDateTime, Timelike, Utc};
+use diesel::prelude::*;
+use log::{error, info};
+
+use crate::config::Retention;
+use crate::db::{self, Aggregate, DbConnection, DATETIME_FORMAT};
+use crate::history::{build_samples, Sample};
+use crate::validators::Status;
+
+#[derive(Clone, Copy)]
+enum Resolution {
+    Hourly,
+    Daily,
+}
+
+impl Resolution {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::Hourly => "hourly",
+            Resolution::Daily => "daily",
+        }
+    }
+
+    /// Start of the bucket holding `date`
+    fn bucket_start(&self, date: NaiveDateTime) -> NaiveDateTime {
+        let start = match self {
+            Resolution::Hourly => date.date().and_hms_opt(date.hour(), 0, 0),
+            Resolution::Daily => date.date().and_hms_opt(0, 0, 0),
+        };
+        start.unwrap()
+    }
+
+    /// Start of the bucket holding `date`, formatted with `DATETIME_FORMAT`
+    fn bucket(&self, date: NaiveDateTime) -> String {
+        self.bucket_start(date).format(DATETIME_FORMAT).to_string()
+    }
+}
+
+#[derive(Default)]
+pub struct MaintenanceSummary {
+    pub(crate) deleted_runs: usize,
+    pub(crate) hourly_buckets: usize,
+    pub(crate) deleted_hourly_buckets: usize,
+    pub(crate) daily_buckets: usize,
+}
+
+fn from_sample(sample: &Sample, resolution: Resolution, period_start: String) -> Aggregate {
+    let status = sample.status.as_deref();
+    let count_status = |expected: Status| (status == Some(expected.as_str())) as i32;
+
+    Aggregate {
+        id: None,
+        resolution: resolution.as_str().to_string(),
+        period_start,
+        name: sample.check.clone(),
+        samples: 1,
+        min_value: sample.value,
+        max_value: sample.value,
+        avg_value: sample.value.map(|value| value as f64),
+        ok_count: count_status(Status::Ok),
+        warning_count: count_status(Status::Warning),
+        alert_count: count_status(Status::Alert),
+    }
+}
+
+/// Merges `other` into `aggregate`, averages are weighted by sample count
+fn combine(aggregate: &mut Aggregate, other: &Aggregate) {
+    let pick = |a: Option<i64>, b: Option<i64>, f: fn(i64, i64) -> i64| match (a, b) {
+        (Some(a), Some(b)) => Some(f(a, b)),
+        (a, b) => a.or(b),
+    };
+
+    aggregate.min_value = pick(aggregate.min_value, other.min_value, i64::min);
+    aggregate.max_value = pick(aggregate.max_value, other.max_value, i64::max);
+    aggregate.avg_value = match (aggregate.avg_value, other.avg_value) {
+        (Some(a), Some(b)) => {
+            let total = (aggregate.samples + other.samples) as f64;
+            Some((a * aggregate.samples as f64 + b * other.samples as f64) / total)
+        }
+        (a, b) => a.or(b),
+    };
+    aggregate.samples += other.samples;
+    aggregate.ok_count += other.ok_count;
+    aggregate.warning_count += other.warning_count;
+    aggregate.alert_count += other.alert_count;
+}
+
+fn group(aggregates: impl Iterator<Item = Aggregate>) -> Vec<Aggregate> {
+    let mut buckets: BTreeMap<(String, String), Aggregate> = BTreeMap::new();
+
+    for aggregate in aggregates {
+        let key = (aggregate.period_start.clone(), aggregate.name.clone());
+        match buckets.get_mut(&key) {
+            Some(bucket) => combine(bucket, &aggregate),
+            None => {
+                buckets.insert(key, aggregate);
+            }
+        }
+    }
+
+    buckets.into_values().collect()
+}
+
+/// Stores the buckets, merging them into existing ones if a previous maintenance created them
+fn save(conn: &mut DbConnection, aggregates: Vec<Aggregate>) -> QueryResult<usize> {
+    let count = aggregates.len();
+
+    for mut aggregate in aggregates {
+        let existing = db::get_aggregate(
+            conn,
+            &aggregate.resolution,
+            &aggregate.period_start,
+            &aggregate.name,
+        )?;
+        if let Some(mut existing) = existing {
+            combine(&mut existing, &aggregate);
+            aggregate = existing;
+        }
+        db::save_aggregate(conn, &aggregate)?;
+    }
+
+    Ok(count)
+}
+
+fn roll_up(
+    conn: &mut DbConnection,
+    retention: &Retention,
+    now: DateTime<Utc>,
+) -> QueryResult<MaintenanceSummary> {
+    let mut summary = MaintenanceSummary::default();
+
+    // Cutoffs are aligned on bucket boundaries so that only complete buckets are rolled up
+    let raw_cutoff = Resolution::Hourly
+        .bucket_start((now - Duration::days(retention.raw_days as i64)).naive_utc());
+    let raw_cutoff = db::timestamp(raw_cutoff.and_utc());
+    let (logs, check_results) = db::get_history(conn, "", &raw_cutoff)?;
+    let samples = build_samples(&logs, &check_results, None);
+    let hourly = samples.iter().filter_map(|sample| {
+        let date = DateTime::parse_from_rfc3339(sample.datetime.as_ref()?).ok()?;
+        let period_start = Resolution::Hourly.bucket(date.naive_utc());
+        Some(from_sample(sample, Resolution::Hourly, period_start))
+    });
+    summary.hourly_buckets = save(conn, group(hourly))?;
+    summary.deleted_runs = db::delete_logs_before(conn, &raw_cutoff)?;
+
+    let hourly_cutoff =
+        Resolution::Daily.bucket((now - Duration::days(retention.hourly_days as i64)).naive_utc());
+    let old_hourly = db::get_aggregates_before(conn, Resolution::Hourly.as_str(), &hourly_cutoff)?;
+    let daily = old_hourly.into_iter().filter_map(|mut aggregate| {
+        let date = NaiveDateTime::parse_from_str(&aggregate.period_start, DATETIME_FORMAT).ok()?;
+        aggregate.id = None;
+        aggregate.resolution = Resolution::Daily.as_str().to_string();
+        aggregate.period_start = Resolution::Daily.bucket(date);
+        Some(aggregate)
+    });
+    summary.daily_buckets = save(conn, group(daily))?;
+    summary.deleted_hourly_buckets =
+        db::delete_aggregates_before(conn, Resolution::Hourly.as_str(), &hourly_cutoff)?;
+
+    Ok(summary)
+}
+
+/// Applies the retention policy if any, then reclaims disk space
+pub fn maintain(
+    conn: &mut DbConnection,
+    retention: Option<&Retention>,
+    now: DateTime<Utc>,
+) -> QueryResult<MaintenanceSummary> {
+    let summary = match retention {
+        Some(retention) => conn.transaction(|conn| roll_up(conn, retention, now))?,
+        None => {
+            info!("No retention policy configured, only vacuuming the database");
+            MaintenanceSummary::default()
+        }
+    };
+
+    db::vacuum(conn)?;
+
+    Ok(summary)
+}
+
+pub fn run_maintenance(db_url: &str, retention: Option<&Retention>) {
+    let mut conn = match db::load_db(db_url) {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("Failed to establish a database connection: {}", e);
+            return;
+        }
+    };
+
+    match maintain(&mut conn, retention, Utc::now()) {
+        Ok(summary) => {
+            let message = format!(
+                "Database maintenance done: {} runs rolled into {} hourly buckets, {} hourly buckets rolled into {} daily buckets",
+                summary.deleted_runs,
+                summary.hourly_buckets,
+                summary.deleted_hourly_buckets,
+                summary.daily_buckets
+            );
+            println!("{}", message);
+            info!("{}", message);
+        }
+        Err(e) => error!("Database maintenance failed: {:?}", e),
+    }
+}
@@ -1,5 +1,29 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    activity_aggregates (id) {
+        id -> Nullable<Integer>,
+        resolution -> Text,
+        period_start -> Text,
+        name -> Text,
+        samples -> Integer,
+        min_value -> Nullable<BigInt>,
+        max_value -> Nullable<BigInt>,
+        avg_value -> Nullable<Double>,
+        ok_count -> Integer,
+        warning_count -> Integer,
+        alert_count -> Integer,
+    }
+}
+
+diesel::table! {
+    django_sessions (login_url) {
+        login_url -> Text,
+        cookies -> Text,
+        saved_at -> Text,
+    }
+}
+
 diesel::table! {
     activity_logs (id) {
         id -> Nullable<Integer>,
@@ -11,5 +35,58 @@ diesel::table! {
         slack_sent -> Bool,
         email_sent -> Bool,
         datetime -> Nullable<Text>,
+        started_at -> Nullable<Text>,
+        finished_at -> Nullable<Text>,
+        duration_ms -> Nullable<Integer>,
+        threshold_profile -> Nullable<Text>,
+        slack_message -> Nullable<Text>,
+        email_body -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    check_results (id) {
+        id -> Nullable<Integer>,
+        log_id -> Integer,
+        name -> Text,
+        status -> Text,
+        message -> Nullable<Text>,
+        url -> Nullable<Text>,
+        value -> Nullable<Text>,
     }
 }
+
+diesel::table! {
+    silences (id) {
+        id -> Nullable<Integer>,
+        check_name -> Text,
+        kind -> Text,
+        expires_at -> Nullable<Text>,
+        reason -> Nullable<Text>,
+        created_by -> Nullable<Text>,
+        created_at -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    state_labels (id) {
+        id -> Nullable<Integer>,
+        source -> Text,
+        column_name -> Text,
+        label -> Text,
+        first_seen -> Text,
+        last_seen -> Text,
+        last_count -> Integer,
+    }
+}
+
+diesel::joinable!(check_results -> activity_logs (log_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    activity_aggregates,
+    activity_logs,
+    check_results,
+    django_sessions,
+    silences,
+    state_labels,
+);
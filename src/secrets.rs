@@ -0,0 +1,111 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+use reqwest::header::HeaderValue;
+use serde_json::Value;
+
+use crate::logging;
+
+const DEFAULT_VAULT_ADDR: &str = "http://127.0.0.1:8200";
+
+/// `VAULT_ADDR`/`VAULT_TOKEN` (or `VAULT_TOKEN_FILE`), `spec` is `<path>#<key>`
+async fn read_vault(spec: &str) -> Result<String, String> {
+    let (path, key) = spec
+        .split_once('#')
+        .ok_or_else(|| format!("expected <path>#<key>, got `{}`", spec))?;
+    let addr = env::var("VAULT_ADDR").unwrap_or_else(|_| DEFAULT_VAULT_ADDR.to_string());
+    let token = match env::var("VAULT_TOKEN") {
+        Ok(token) => token,
+        Err(_) => read_file("VAULT_TOKEN_FILE")?.ok_or("VAULT_TOKEN is not set")?,
+    };
+
+    logging::redact(&[&token]);
+
+    let mut token_header = HeaderValue::from_str(&token).map_err(|e| e.to_string())?;
+    token_header.set_sensitive(true);
+
+    let url = format!(
+        "{}/v1/{}",
+        addr.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+    let response: Value = reqwest::Client::new()
+        .get(&url)
+        .header("X-Vault-Token", token_header)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // KV version 2 nests the secret in `data.data`, version 1 in `data`
+    let data = &response["data"];
+    data["data"][key]
+        .as_str()
+        .or(data[key].as_str())
+        .map(|value| value.to_string())
+        .ok_or_else(|| format!("no `{}` key at {}", key, path))
+}
+
+fn read_file(file_var: &str) -> Result<Option<String>, String> {
+    match env::var(file_var) {
+        Ok(path) => fs::read_to_string(&path)
+            .map(|content| Some(content.trim().to_string()))
+            .map_err(|e| format!("failed to read {}: {}", path, e)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn run_command(command: &str) -> Result<String, String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    if !output.status.success() {
+        return Err(format!("`{}` exited with {}", command, output.status));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Looks up `NAME`, then the path in `NAME_FILE`, the output of `NAME_COMMAND`,
+/// and the Vault secret in `NAME_VAULT`. Values are redacted from the logs
+pub async fn load(name: &str) -> Result<Option<String>, String> {
+    let value = if let Ok(value) = env::var(name) {
+        Some(value)
+    } else if let Some(value) = read_file(&format!("{}_FILE", name))? {
+        Some(value)
+    } else if let Ok(command) = env::var(format!("{}_COMMAND", name)) {
+        Some(run_command(&command)?)
+    } else if let Ok(spec) = env::var(format!("{}_VAULT", name)) {
+        Some(read_vault(&spec).await?)
+    } else {
+        None
+    };
+
+    if let Some(value) = &value {
+        logging::redact(&[value]);
+    }
+    Ok(value)
+}
+
+pub async fn required(name: &str) -> String {
+    match load(name).await {
+        Ok(Some(value)) => value,
+        Ok(None) => panic!(
+            "{} is not set, use {0}, {0}_FILE, {0}_COMMAND or {0}_VAULT",
+            name
+        ),
+        Err(e) => panic!("Failed to load {}: {}", name, e),
+    }
+}
+
+pub async fn optional(name: &str) -> Option<String> {
+    load(name)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to load {}: {}", name, e))
+}
@@ -0,0 +1,178 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::config::Config;
+use crate::db::{delete_silence, insert_silence, DbConnection, Silence, DATETIME_FORMAT};
+
+/// Check name used by maintenance windows covering every check
+const ALL_CHECKS: &str = "*";
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SilenceKind {
+    Ack,
+    Silence,
+    Maintenance,
+}
+
+impl SilenceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SilenceKind::Ack => "ack",
+            SilenceKind::Silence => "silence",
+            SilenceKind::Maintenance => "maintenance",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<SilenceKind> {
+        match kind {
+            "ack" => Some(SilenceKind::Ack),
+            "silence" => Some(SilenceKind::Silence),
+            "maintenance" => Some(SilenceKind::Maintenance),
+            _ => None,
+        }
+    }
+}
+
+/// A silence without duration lasts until the check is resolved, so does one whose end can't
+/// be represented
+pub fn new_silence(
+    check_name: &str,
+    kind: SilenceKind,
+    duration: Option<Duration>,
+    reason: Option<String>,
+    created_by: Option<String>,
+) -> Silence {
+    Silence {
+        id: None,
+        check_name: check_name.to_string(),
+        kind: kind.as_str().to_string(),
+        expires_at: duration
+            .and_then(|d| Utc::now().checked_add_signed(d))
+            .map(|expires_at| expires_at.format(DATETIME_FORMAT).to_string()),
+        reason,
+        created_by,
+        created_at: None,
+    }
+}
+
+/// Maintenance windows from the config are never stored, they act as in-memory silences
+pub fn maintenance_silences(config: &Config, now: DateTime<Utc>) -> Vec<Silence> {
+    let mut silences = Vec::new();
+
+    for window in config.maintenance.iter().filter(|w| w.is_active(now)) {
+        let check_names = if window.checks.is_empty() {
+            vec![ALL_CHECKS.to_string()]
+        } else {
+            window.checks.clone()
+        };
+
+        for check_name in check_names {
+            silences.push(Silence {
+                id: None,
+                check_name,
+                kind: SilenceKind::Maintenance.as_str().to_string(),
+                expires_at: None,
+                reason: window.reason.clone(),
+                created_by: None,
+                created_at: None,
+            });
+        }
+    }
+
+    silences
+}
+
+pub fn find_silence<'a>(check_name: &str, silences: &'a [Silence]) -> Option<&'a Silence> {
+    silences
+        .iter()
+        .find(|s| s.check_name == check_name || s.check_name == ALL_CHECKS)
+}
+
+pub fn is_silenced(check_name: &str, silences: &[Silence]) -> bool {
+    find_silence(check_name, silences).is_some()
+}
+
+pub fn describe(silence: &Silence) -> String {
+    let kind = SilenceKind::parse(&silence.kind);
+    let action = match kind {
+        Some(SilenceKind::Ack) => "acknowledged",
+        Some(SilenceKind::Maintenance) => "in maintenance",
+        _ => "silenced",
+    };
+    let author = match &silence.created_by {
+        Some(user) => format!(" by {}", user),
+        None => String::new(),
+    };
+    let expiry = match (&silence.expires_at, kind) {
+        (Some(date), _) => format!(" until {} UTC", date),
+        (None, Some(SilenceKind::Maintenance)) => String::new(),
+        (None, _) => " until resolved".to_string(),
+    };
+    let reason = match &silence.reason {
+        Some(reason) => format!(" ({})", reason),
+        None => String::new(),
+    };
+
+    format!("{}{}{}{}", action, author, expiry, reason)
+}
+
+pub fn add(
+    conn: &mut DbConnection,
+    check_names: &[&str],
+    check_name: &str,
+    duration: Option<std::time::Duration>,
+    reason: Option<String>,
+) {
+    if !check_names.contains(&check_name) {
+        println!(
+            "Warning: \"{}\" is not a known check ({})",
+            check_name,
+            check_names.join(", ")
+        );
+    }
+
+    let duration = match duration.map(Duration::from_std) {
+        None => None,
+        Some(Ok(d)) if Utc::now().checked_add_signed(d).is_some() => Some(d),
+        Some(_) => {
+            println!("Failed to add silence: the duration is too long");
+            return;
+        }
+    };
+    let created_by = std::env::var("USER").ok();
+    let silence = new_silence(
+        check_name,
+        SilenceKind::Silence,
+        duration,
+        reason,
+        created_by,
+    );
+    let description = describe(&silence);
+
+    match insert_silence(conn, silence) {
+        Ok(_) => println!("{} {}", check_name, description),
+        Err(e) => println!("Failed to add silence: {:?}", e),
+    }
+}
+
+pub fn list(silences: &[Silence]) {
+    if silences.is_empty() {
+        println!("No active silence");
+        return;
+    }
+
+    for silence in silences {
+        let id = match silence.id {
+            Some(id) => format!("#{}", id),
+            None => "config".to_string(),
+        };
+        println!("{:>8}  {}: {}", id, silence.check_name, describe(silence));
+    }
+}
+
+pub fn remove(conn: &mut DbConnection, silence_id: i32) {
+    match delete_silence(conn, silence_id) {
+        Ok(0) => println!("No silence with id #{}", silence_id),
+        Ok(_) => println!("Silence #{} removed", silence_id),
+        Err(e) => println!("Failed to remove silence: {:?}", e),
+    }
+}
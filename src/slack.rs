@@ -1,9 +1,15 @@
-use reqwest;
-use serde_json::json;
+use std::error::Error;
 
-use crate::db::LogEntry;
+use serde_json::{json, Value as JsonValue};
+
+use crate::db::{LogEntry, Silence};
+use crate::silence::{describe, find_silence};
 use crate::validators::{Status, UnitValidationResult, Value};
 
+const SECTION_MAX_LENGTH: usize = 3000;
+/// Slack rejects the whole message above this many blocks
+const MAX_BLOCKS: usize = 50;
+
 fn get_corresponding_value(name: &str, log_entry: &LogEntry) -> Value {
     match name {
         "Validated payments" => Value::Count(log_entry.payments as usize),
@@ -15,10 +21,38 @@ fn get_corresponding_value(name: &str, log_entry: &LogEntry) -> Value {
     }
 }
 
+/// First `limit` failing rows of a check, linked to their admin change page
+fn drill_down(result: &UnitValidationResult, limit: usize) -> String {
+    let mut lines = String::new();
+
+    for item in result.failing_items.iter().take(limit) {
+        let id = item.id.as_deref().unwrap_or("row");
+        let link = match &item.url {
+            Some(url) => format!("<{}|{}>", url, id),
+            None => id.to_string(),
+        };
+        let mut line = format!(":blank: • {} `{}`", link, item.state);
+        for detail in &item.details {
+            line.push_str(&format!(" {}", detail));
+        }
+        lines.push_str(&line);
+        lines.push('\n');
+    }
+
+    let remaining = result.failing_items.len().saturating_sub(limit);
+    if remaining > 0 {
+        lines.push_str(&format!(":blank: _and {} more_\n", remaining));
+    }
+
+    lines
+}
+
 pub fn create_message(
     validation_results: &Vec<(UnitValidationResult, String)>,
     last_log: Option<LogEntry>,
+    silences: &[Silence],
     is_test_mode: bool,
+    drill_down_rows: usize,
 ) -> String {
     let mut should_alert_channel = false;
     let mut message = "".to_string();
@@ -28,7 +62,9 @@ pub fn create_message(
     }
 
     for (result, url) in validation_results {
-        if result.status == Status::Alert {
+        let silence = find_silence(&result.name, silences);
+
+        if result.status == Status::Alert && silence.is_none() {
             should_alert_channel = true;
         }
 
@@ -57,30 +93,162 @@ pub fn create_message(
             Status::Alert => ":square_x:",
         };
 
-        let link = format!(" <{}| View >\n", url);
+        let link = if url.is_empty() {
+            String::new()
+        } else {
+            format!(" <{}| View >", url)
+        };
+
+        let silence_note = match silence {
+            Some(silence) if result.status != Status::Ok => format!(" _{}_", describe(silence)),
+            _ => String::new(),
+        };
 
         message.push_str(&format!(
-            "{}{} {}: {} {}",
-            status_symbol, trend_icon, result.name, result.message, link
+            "{}{} {}: {} {}{}\n",
+            status_symbol, trend_icon, result.name, result.message, link, silence_note
         ));
+        if result.status != Status::Ok {
+            message.push_str(&drill_down(result, drill_down_rows));
+        }
     }
 
     if should_alert_channel {
-        message.push_str(&format!("<!channel>"));
+        message.push_str("<!channel>");
     }
 
     message
 }
 
-pub async fn post_message(token: &str, channel: &str, message: &str) -> Result<(), reqwest::Error> {
+/// Wraps the message in Block Kit and adds Ack/Silence buttons under each unsilenced alert
+pub fn create_blocks(
+    validation_results: &Vec<(UnitValidationResult, String)>,
+    message: &str,
+    silences: &[Silence],
+) -> JsonValue {
+    let mut blocks = section_blocks(message);
+
+    for (result, _) in validation_results {
+        if result.status != Status::Alert || find_silence(&result.name, silences).is_some() {
+            continue;
+        }
+        // The remaining alerts can still be silenced from the CLI
+        if blocks.len() + 2 > MAX_BLOCKS {
+            break;
+        }
+
+        blocks.push(json!({
+            "type": "context",
+            "elements": [{"type": "mrkdwn", "text": format!("*{}*", result.name)}],
+        }));
+        blocks.push(json!({
+            "type": "actions",
+            "elements": [
+                create_button("Ack", "ack", &result.name),
+                create_button("Silence 1h", "silence_1h", &result.name),
+                create_button("Silence until resolved", "silence_until_resolved", &result.name),
+            ],
+        }));
+    }
+
+    JsonValue::Array(blocks)
+}
+
+/// Splits the message on line boundaries to fit Slack's per-section text limit, longer lines
+/// are truncated
+pub fn section_blocks(message: &str) -> Vec<JsonValue> {
+    let mut sections = vec![String::new()];
+
+    for line in message.lines() {
+        let line = truncate(line, SECTION_MAX_LENGTH - 1);
+        let current = sections.last_mut().unwrap();
+        if !current.is_empty() && current.len() + line.len() + 1 > SECTION_MAX_LENGTH {
+            sections.push(String::new());
+        }
+        let current = sections.last_mut().unwrap();
+        current.push_str(&line);
+        current.push('\n');
+    }
+    if sections.len() > MAX_BLOCKS {
+        sections.truncate(MAX_BLOCKS - 1);
+        sections.push("_Message truncated, see the logs for the rest_\n".to_string());
+    }
+
+    sections
+        .into_iter()
+        .map(|text| {
+            json!({
+                "type": "section",
+                "text": {"type": "mrkdwn", "text": text},
+            })
+        })
+        .collect()
+}
+
+fn truncate(line: &str, max_length: usize) -> String {
+    if line.len() <= max_length {
+        return line.to_string();
+    }
+    let mut end = max_length - '…'.len_utf8();
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &line[..end])
+}
+
+fn create_button(text: &str, action_id: &str, check_name: &str) -> JsonValue {
+    json!({
+        "type": "button",
+        "text": {"type": "plain_text", "text": text},
+        "action_id": action_id,
+        "value": check_name,
+    })
+}
+
+/// Body of the `chat.postMessage` call
+pub fn message_payload(channel: &str, message: &str, blocks: &JsonValue) -> JsonValue {
+    json!({
+        "channel": channel,
+        "text": message,
+        "blocks": blocks,
+        "unfurl_links": false,
+    })
+}
+
+pub async fn post_message(
+    token: &str,
+    channel: &str,
+    message: &str,
+    blocks: &JsonValue,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     let client = reqwest::Client::new();
     let res = client
         .post("https://slack.com/api/chat.postMessage")
         .bearer_auth(token)
+        .json(&message_payload(channel, message, blocks))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    // Rejected messages, e.g. `invalid_blocks`, still come back with a 200
+    let body: JsonValue = res.json().await?;
+    if body["ok"].as_bool() == Some(true) {
+        Ok(())
+    } else {
+        let reason = body["error"].as_str().unwrap_or("unknown error");
+        Err(format!("Slack API error: {}", reason).into())
+    }
+}
+
+/// Posts a follow-up through the `response_url` of an interaction payload
+pub async fn post_response(response_url: &str, message: &str) -> Result<(), reqwest::Error> {
+    let client = reqwest::Client::new();
+    let res = client
+        .post(response_url)
         .json(&json!({
-            "channel": channel,
+            "response_type": "in_channel",
+            "replace_original": false,
             "text": message,
-            "unfurl_links": false,
         }))
         .send()
         .await?;
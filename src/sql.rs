@@ -0,0 +1,55 @@
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::Text;
+
+use crate::db::{load_db, DbConnection};
+
+#[derive(QueryableByName)]
+struct Rows {
+    #[diesel(sql_type = Text)]
+    rows: String,
+}
+
+/// Aggregates the rows of `query` into a JSON array of objects keyed by `columns`, so that
+/// SQL sources are parsed like JSON ones
+fn aggregate(query: &str, columns: &[String], is_postgres: bool) -> String {
+    let fields = columns
+        .iter()
+        .map(|column| format!("'{0}', q.\"{0}\"", column))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let query = query.trim().trim_end_matches(';');
+
+    if is_postgres {
+        format!(
+            "SELECT coalesce(json_agg(json_build_object({})), '[]')::text AS rows FROM ({}) AS q",
+            fields, query
+        )
+    } else {
+        format!(
+            "SELECT json_group_array(json_object({})) AS rows FROM ({}) AS q",
+            fields, query
+        )
+    }
+}
+
+/// Runs `query` in a read-only session, the database is never written to
+pub fn query_rows(
+    db_url: &str,
+    query: &str,
+    columns: &[String],
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let rows = match load_db(db_url)? {
+        DbConnection::Postgres(mut conn) => {
+            let query = aggregate(query, columns, true);
+            conn.build_transaction()
+                .read_only()
+                .run(|conn| sql_query(query).get_result::<Rows>(conn))?
+        }
+        DbConnection::Sqlite(mut conn) => {
+            sql_query("PRAGMA query_only = ON").execute(&mut conn)?;
+            sql_query(aggregate(query, columns, false)).get_result::<Rows>(&mut conn)?
+        }
+    };
+    Ok(rows.rows)
+}
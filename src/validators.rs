@@ -1,10 +1,15 @@
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter, Result};
 
 use chrono::prelude::*;
 use chrono_tz::Europe::Paris;
 
-use crate::parser::{EmailStatuses, VoucherStatuses};
-use crate::parser::{PageResults, PaymentStatuses};
+use crate::auth::InvalidCredentials;
+use crate::config::{Config, SourceFormat};
+use crate::labels::NewLabel;
+use crate::parser::{format_age, EmailStatuses, FailingItem, StuckRows, VoucherStatuses};
+use crate::parser::{is_strict_column, BrokenSource, PageResults, PaymentStatuses};
+use crate::parser::{Reconciliation, WindowedRows};
 
 #[derive(PartialEq)]
 pub enum Status {
@@ -13,6 +18,16 @@ pub enum Status {
     Alert,
 }
 
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Alert => "alert",
+        }
+    }
+}
+
 pub enum Value {
     Count(usize),
     Bool(bool),
@@ -27,30 +42,121 @@ impl Display for Value {
     }
 }
 
+/// Lowest values still considered ok or warning, the check alerts below `critical`
+pub struct Limits {
+    pub(crate) warning: usize,
+    pub(crate) critical: usize,
+    pub(crate) max: usize,
+}
+
 pub struct UnitValidationResult {
     pub(crate) name: String,
     pub(crate) status: Status,
     pub(crate) message: String,
     pub(crate) value: Value,
+    pub(crate) limits: Option<Limits>,
+    /// Rows in a bad state, for the drill-down under alerts
+    pub(crate) failing_items: Vec<FailingItem>,
 }
 
-fn get_threshold(threshold_day: usize, threshold_night: usize) -> usize {
-    let paris_time = Local::now().with_timezone(&Paris);
-    let hour = paris_time.hour();
-    if hour >= 8 && hour < 23 {
-        threshold_day
-    } else {
-        threshold_night
-    }
+/// Names of the results returned by `validate`, in order
+pub const CHECK_NAMES: [&str; 9] = [
+    "Validated payments",
+    "Paid vouchers",
+    "PDF count",
+    "Email count",
+    "Purchase website",
+    "Celery",
+    CREDENTIALS_CHECK,
+    SCRAPER_CHECK,
+    LABELS_CHECK,
+];
+
+pub const CREDENTIALS_CHECK: &str = "Beebot credentials";
+pub const SCRAPER_CHECK: &str = "Beebot scraper";
+pub const LABELS_CHECK: &str = "State labels";
+
+/// Checks about the scraping itself rather than a page, they may have no URL
+pub const META_CHECKS: [&str; 3] = [CREDENTIALS_CHECK, SCRAPER_CHECK, LABELS_CHECK];
+
+/// Rows in an admin changelist page, the payment ratio of unwindowed HTML sources is out of it
+const ADMIN_PAGE_SIZE: usize = 100;
+
+/// Checks computed from each source, see `parser::extract_metrics`. "Paid vouchers" is out of
+/// the imported paid vouchers, so it depends on both voucher sources and on both their windows
+const SOURCE_CHECKS: [(&str, &[&str]); 5] = [
+    ("payments", &["Validated payments"]),
+    ("vouchers", &["Paid vouchers"]),
+    (
+        "paid_vouchers",
+        &["Paid vouchers", "PDF count", "Email count"],
+    ),
+    ("purchase_website", &["Purchase website"]),
+    ("celery", &["Celery"]),
+];
+
+/// Warning thresholds are looser at night, when fewer orders come in
+#[derive(Clone, Copy)]
+pub enum ThresholdProfile {
+    Day,
+    Night,
 }
 
-pub fn validate(pages: &PageResults) -> Vec<(UnitValidationResult, String)> {
-    let threshold = get_threshold(75, 50);
+impl ThresholdProfile {
+    /// Day runs from 8:00 to 23:00, Paris time
+    pub fn at(date: DateTime<Utc>) -> ThresholdProfile {
+        let hour = date.with_timezone(&Paris).hour();
+        if (8..23).contains(&hour) {
+            ThresholdProfile::Day
+        } else {
+            ThresholdProfile::Night
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThresholdProfile::Day => "day",
+            ThresholdProfile::Night => "night",
+        }
+    }
+
+    fn threshold(&self) -> usize {
+        match self {
+            ThresholdProfile::Day => 75,
+            ThresholdProfile::Night => 50,
+        }
+    }
+}
 
+/// Checks of sources with invalid credentials are left out: their pages were not fetched,
+/// so they would alert on zero counts. The credentials check alerts instead, and likewise the
+/// scraper check for the sources whose structure changed
+pub fn validate(
+    config: &Config,
+    pages: &PageResults,
+    profile: ThresholdProfile,
+    invalid_credentials: &BTreeMap<String, InvalidCredentials>,
+    new_labels: &[NewLabel],
+) -> Vec<(UnitValidationResult, String)> {
+    let threshold = profile.threshold();
+
+    // API pages have their own size and SQL queries give exact counts, only the admin
+    // changelist is known to be full
+    let payment_rows = match (
+        pages.windows.get("payments"),
+        config.source("payments").format,
+    ) {
+        (Some(windowed), _) => windowed.rows,
+        (None, SourceFormat::Json | SourceFormat::Sql) => {
+            pages.source_rows.get("payments").copied().unwrap_or(0)
+        }
+        (None, SourceFormat::Html) => ADMIN_PAGE_SIZE,
+    };
     let payments_result = validate_payment_status(
         "Validated payments",
         threshold,
         pages.validated_payments_count,
+        payment_rows,
     );
     let paid_vouchers_result = validate_voucher_status(
         "Paid vouchers",
@@ -62,27 +168,321 @@ pub fn validate(pages: &PageResults) -> Vec<(UnitValidationResult, String)> {
         "PDF count",
         threshold,
         pages.pdf_count,
-        pages.not_imported_count,
+        pages.not_imported_count - pages.unknown_pdf_count,
     );
     let emails_result = validate_email_status(
         "Email count",
         threshold,
         pages.email_check_count,
-        pages.not_imported_count,
+        pages.not_imported_count - pages.unknown_email_count,
     );
     let purchase_website_result =
         validate_purchase_website_status("Purchase website", pages.is_website_online);
 
     let celery_statuses = validate_celery_statuses("Celery", pages.is_celery_online);
 
-    vec![
+    let skipped_checks = skipped_checks(config, invalid_credentials);
+    let broken_checks = broken_checks(config, &pages.broken_sources);
+    let mut results: Vec<(UnitValidationResult, String)> = vec![
         (payments_result, pages.url_validated_payments.clone()),
         (paid_vouchers_result, pages.url_vouchers_count.clone()),
         (pdf_count_result, pages.url_pdf_count.clone()),
         (emails_result, pages.url_email_check_count.clone()),
         (purchase_website_result, pages.url_website.clone()),
         (celery_statuses, pages.url_celery.clone()),
-    ]
+    ];
+
+    for (result, _) in results.iter_mut() {
+        if let Some(items) = pages.failing_items.get(&result.name) {
+            result.failing_items = items.clone();
+        }
+    }
+
+    for (result, _) in results.iter_mut() {
+        let windows: Vec<(&str, &WindowedRows)> = SOURCE_CHECKS
+            .iter()
+            .filter(|(_, checks)| checks.contains(&result.name.as_str()))
+            .filter_map(|(source, _)| Some((*source, pages.windows.get(*source)?)))
+            .collect();
+        if !windows.is_empty() {
+            apply_windows(result, &windows, profile);
+        }
+    }
+
+    for stuck in &pages.stuck {
+        results.push((validate_stuck(stuck), stuck.url.clone()));
+    }
+    for reconciliation in &pages.reconciliations {
+        let result = validate_reconciliation(reconciliation);
+        results.push((result, reconciliation.url.clone()));
+    }
+    results.retain(|(result, _)| {
+        let name = result.name.as_str();
+        !skipped_checks.contains(&name) && !broken_checks.contains(&name)
+    });
+
+    results.push(validate_credentials(
+        CREDENTIALS_CHECK,
+        invalid_credentials,
+        &skipped_checks,
+    ));
+    results.push(validate_scraper(
+        SCRAPER_CHECK,
+        &pages.broken_sources,
+        &broken_checks,
+        new_labels,
+    ));
+    results.push((validate_labels(LABELS_CHECK, new_labels), String::new()));
+    results
+}
+
+/// A quiet window is not an outage: without rows on either side the ratios are meaningless,
+/// and fewer rows than the profile expects is a warning. Windows are named after their source
+/// when both sides of a ratio have one
+fn apply_windows(
+    result: &mut UnitValidationResult,
+    windows: &[(&str, &WindowedRows)],
+    profile: ThresholdProfile,
+) {
+    if windows.iter().any(|(_, windowed)| windowed.rows == 0) {
+        result.status = Status::Ok;
+    }
+
+    for (source, windowed) in windows {
+        let expected = windowed.window.expected.at(profile);
+        if windowed.rows < expected {
+            if result.status == Status::Ok {
+                result.status = Status::Warning;
+            }
+            result
+                .message
+                .push_str(&format!(" `{}/{} ROWS EXPECTED`", windowed.rows, expected));
+        }
+        result.message.push_str(&format!(
+            " in the last {}",
+            humantime::format_duration(windowed.window.duration)
+        ));
+        if windows.len() > 1 {
+            result.message.push_str(&format!(" ({})", source));
+        }
+    }
+}
+
+fn source_checks(is_affected: impl Fn(&str) -> bool) -> Vec<&'static str> {
+    let mut checks = Vec::new();
+    for (_, source_checks) in SOURCE_CHECKS
+        .iter()
+        .filter(|(source, _)| is_affected(source))
+    {
+        for check in source_checks.iter() {
+            if !checks.contains(check) {
+                checks.push(*check);
+            }
+        }
+    }
+    checks
+}
+
+/// Stuck and reconcile checks reading one of the sources, both sides for reconciliations
+fn rule_checks(config: &Config, is_affected: impl Fn(&str) -> bool) -> Vec<&str> {
+    let stuck = config
+        .stuck
+        .iter()
+        .filter(|rule| is_affected(&rule.source))
+        .map(|rule| rule.name.as_str());
+    let reconcile = config
+        .reconcile
+        .iter()
+        .filter(|rule| is_affected(&rule.from.source) || is_affected(&rule.to.source))
+        .map(|rule| rule.name.as_str());
+    stuck.chain(reconcile).collect()
+}
+
+pub fn skipped_checks<'a>(
+    config: &'a Config,
+    invalid_credentials: &BTreeMap<String, InvalidCredentials>,
+) -> Vec<&'a str> {
+    let is_affected = |source: &str| invalid_credentials.contains_key(source);
+    let mut checks = source_checks(is_affected);
+    checks.extend(rule_checks(config, is_affected));
+    checks
+}
+
+/// Checks of the sources the scraper can't parse, their values would be wrong
+pub fn broken_checks<'a>(
+    config: &'a Config,
+    broken_sources: &BTreeMap<String, BrokenSource>,
+) -> Vec<&'a str> {
+    let is_affected = |source: &str| broken_sources.contains_key(source);
+    let mut checks = source_checks(is_affected);
+    checks.extend(rule_checks(config, is_affected));
+    checks
+}
+
+fn validate_credentials(
+    name: &str,
+    invalid_credentials: &BTreeMap<String, InvalidCredentials>,
+    skipped_checks: &[&str],
+) -> (UnitValidationResult, String) {
+    let Some(first) = invalid_credentials.values().next() else {
+        let result = UnitValidationResult {
+            name: name.to_string(),
+            status: Status::Ok,
+            message: "Sources accepted Beebot credentials".to_string(),
+            value: Value::Bool(true),
+            limits: None,
+            failing_items: Vec::new(),
+        };
+        return (result, String::new());
+    };
+
+    let sources = invalid_credentials
+        .iter()
+        .map(|(source, e)| format!("`{}` ({})", source, e.reason))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let result = UnitValidationResult {
+        name: name.to_string(),
+        status: Status::Alert,
+        message: format!(
+            "Invalid for {}, skipped checks: {}",
+            sources,
+            skipped_checks.join(", ")
+        ),
+        value: Value::Bool(false),
+        limits: None,
+        failing_items: Vec::new(),
+    };
+    (result, first.url.clone())
+}
+
+/// Unknown labels of the strict columns alert once, when first seen: their rows are left out
+/// of the ratios, which keep being computed
+fn validate_scraper(
+    name: &str,
+    broken_sources: &BTreeMap<String, BrokenSource>,
+    broken_checks: &[&str],
+    new_labels: &[NewLabel],
+) -> (UnitValidationResult, String) {
+    let unknown_labels: Vec<String> = new_labels
+        .iter()
+        .filter(|new| is_strict_column(&new.source, &new.column))
+        .map(|new| format!("`{}` in {}.{}", new.label, new.source, new.column))
+        .collect();
+    let mut result = UnitValidationResult {
+        name: name.to_string(),
+        status: Status::Ok,
+        message: "Sources have the expected structure".to_string(),
+        value: Value::Bool(true),
+        limits: None,
+        failing_items: Vec::new(),
+    };
+    if broken_sources.is_empty() && unknown_labels.is_empty() {
+        return (result, String::new());
+    }
+
+    let mut problems = Vec::new();
+    if !broken_sources.is_empty() {
+        let sources = broken_sources
+            .iter()
+            .map(|(source, broken)| format!("`{}` ({})", source, broken.problems.join(", ")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        problems.push(format!(
+            "Broken for {}, skipped checks: {}",
+            sources,
+            broken_checks.join(", ")
+        ));
+    }
+    if !unknown_labels.is_empty() {
+        problems.push(format!(
+            "Unknown labels left out of the ratios: {}",
+            unknown_labels.join(", ")
+        ));
+    }
+    result.status = Status::Alert;
+    result.message = problems.join(". ");
+    result.value = Value::Bool(false);
+    let url = broken_sources
+        .values()
+        .next()
+        .map_or_else(String::new, |broken| broken.url.clone());
+    (result, url)
+}
+
+/// Warns once per label, the next runs know it
+fn validate_labels(name: &str, new_labels: &[NewLabel]) -> UnitValidationResult {
+    let mut result = UnitValidationResult {
+        name: name.to_string(),
+        status: Status::Ok,
+        message: "No new labels".to_string(),
+        value: Value::Count(new_labels.len()),
+        limits: None,
+        failing_items: Vec::new(),
+    };
+
+    if !new_labels.is_empty() {
+        let labels = new_labels
+            .iter()
+            .map(|new| {
+                format!(
+                    "`{}` in {}.{} (rows: {})",
+                    new.label, new.source, new.column, new.rows
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        result.status = Status::Warning;
+        result.message = format!("New labels: {}", labels);
+    }
+
+    result
+}
+
+fn validate_stuck(stuck: &StuckRows) -> UnitValidationResult {
+    let sla = humantime::format_duration(stuck.rule.sla);
+    let mut result = UnitValidationResult {
+        name: stuck.rule.name.clone(),
+        status: Status::Ok,
+        message: format!("`0 STUCK` over {}", sla),
+        value: Value::Count(stuck.items.len()),
+        limits: None,
+        failing_items: stuck.items.clone(),
+    };
+
+    if let Some(oldest) = stuck.oldest {
+        result.status = Status::Alert;
+        result.message = format!(
+            "`{} STUCK` over {}, oldest for {}",
+            stuck.items.len(),
+            sla,
+            format_age(oldest)
+        );
+    }
+
+    result
+}
+
+fn validate_reconciliation(reconciliation: &Reconciliation) -> UnitValidationResult {
+    let orphans = reconciliation.orphans.len();
+    UnitValidationResult {
+        name: reconciliation.rule.name.clone(),
+        status: if orphans == 0 {
+            Status::Ok
+        } else {
+            Status::Alert
+        },
+        message: format!(
+            "`{}/{} ORPHANS` in {} without {}",
+            orphans,
+            reconciliation.checked,
+            reconciliation.rule.from.source,
+            reconciliation.rule.to.source
+        ),
+        value: Value::Count(orphans),
+        limits: None,
+        failing_items: reconciliation.orphans.clone(),
+    }
 }
 
 fn validate_pdf_count(
@@ -96,6 +496,8 @@ fn validate_pdf_count(
         status: Status::Alert,
         message: "".to_string(),
         value: Value::Count(0),
+        limits: None,
+        failing_items: Vec::new(),
     };
 
     // Arbitrary value to not scare the team with a warning icon
@@ -112,6 +514,11 @@ fn validate_pdf_count(
         result.status = Status::Alert;
     }
     result.value = Value::Count(pdf_count);
+    result.limits = Some(Limits {
+        warning: fixed_threshold_for_ok,
+        critical: relative_threshold_for_warning,
+        max: max_possible_count,
+    });
     result.message = format!("`{}/{}`", pdf_count, max_possible_count);
 
     result
@@ -121,16 +528,19 @@ fn validate_payment_status(
     name: &str,
     threshold: usize,
     statuses: PaymentStatuses,
+    rows: usize,
 ) -> UnitValidationResult {
     let mut result = UnitValidationResult {
         name: name.to_string(),
         status: Status::Alert,
         message: "".to_string(),
         value: Value::Count(0),
+        limits: None,
+        failing_items: Vec::new(),
     };
 
     let validated_count = statuses.validated;
-    let minimum_paid_expected = 100 - statuses.group;
+    let minimum_paid_expected = rows.saturating_sub(statuses.group + statuses.unknown);
 
     if validated_count >= 85 * minimum_paid_expected / 100 {
         result.status = Status::Ok;
@@ -149,7 +559,17 @@ fn validate_payment_status(
         statuses.cancelled,
         statuses.group
     );
+    if statuses.unknown > 0 {
+        result
+            .message
+            .push_str(&format!(" `{} UNKNOWN`", statuses.unknown));
+    }
     result.value = Value::Count(validated_count);
+    result.limits = Some(Limits {
+        warning: 85 * minimum_paid_expected / 100,
+        critical: threshold * minimum_paid_expected / 100 + 1,
+        max: minimum_paid_expected,
+    });
 
     result
 }
@@ -165,6 +585,8 @@ fn validate_voucher_status(
         status: Status::Alert,
         message: "".to_string(),
         value: Value::Count(0),
+        limits: None,
+        failing_items: Vec::new(),
     };
 
     let total_vouchers = max_possible_value;
@@ -183,6 +605,11 @@ fn validate_voucher_status(
     };
 
     result.value = Value::Count(vouchers.paid);
+    result.limits = Some(Limits {
+        warning: total_vouchers,
+        critical: threshold * total_vouchers / 100 + 1,
+        max: total_vouchers,
+    });
 
     result.message = format!(
         "`{}/{} PAID`, `{} ERROR`, `{} OTHER`",
@@ -203,6 +630,8 @@ fn validate_email_status(
         status: Status::Alert,
         message: "".to_string(),
         value: Value::Count(0),
+        limits: None,
+        failing_items: Vec::new(),
     };
 
     let total_emails = max_possible_value;
@@ -221,6 +650,11 @@ fn validate_email_status(
     };
 
     result.value = Value::Count(statuses.sent);
+    result.limits = Some(Limits {
+        warning: total_emails,
+        critical: threshold * total_emails / 100 + 1,
+        max: total_emails,
+    });
 
     result.message = format!(
         "`{}/{} SENT`, `{} NOT SENT`, `{} BULK`",
@@ -236,6 +670,8 @@ fn validate_purchase_website_status(name: &str, is_ok: bool) -> UnitValidationRe
         status: Status::Alert,
         message: "passed".to_string(),
         value: Value::Bool(false),
+        limits: None,
+        failing_items: Vec::new(),
     };
 
     match is_ok {
@@ -259,6 +695,8 @@ fn validate_celery_statuses(name: &str, is_online: bool) -> UnitValidationResult
         status: Status::Alert,
         message: "Celery status: `OFFLINE`".to_string(),
         value: Value::Bool(false),
+        limits: None,
+        failing_items: Vec::new(),
     };
 
     if is_online {